@@ -0,0 +1,191 @@
+use std::io::Write;
+
+use gel::*;
+
+const USAGE: &str = "Usage: gel <pipeline.json> <input.svg|dxf|nc|geojson|wkt> \
+<output.svg|dxf|pdf|png|plt|stl|geojson|wkt> \
+[--param name=value]... [--group name]... [--tolerance value] [--trace trace.json] \
+[--plate group=thickness] [--height group=height]...";
+
+fn parse_param(arg: &str) -> Result<(String, serde_json::Value), String> {
+    let Some((name, value)) = arg.split_once('=') else {
+        return Err(format!("Expected name=value but got '{}'.", arg));
+    };
+
+    // Anything that isn't valid JSON is passed through as a string
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+
+    Ok((name.to_string(), value))
+}
+
+/// A `group=length` argument, in the unit of the shapes.
+fn parse_length(arg: &str) -> Result<(String, f64), String> {
+    let (name, value) = parse_param(arg)?;
+    let Some(length) = value.as_f64() else {
+        return Err(format!(
+            "Expected a number after '{}=' but got '{}'.",
+            name, value
+        ));
+    };
+
+    Ok((name, length))
+}
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut params = Vec::new();
+    let mut output_groups = Vec::new();
+    let mut tolerance = None;
+    let mut trace_path = None;
+    let mut plate = None;
+    let mut heights = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--param" => {
+                let value = args.next().ok_or(USAGE)?;
+                params.push(parse_param(&value)?);
+            }
+            "--group" => output_groups.push(args.next().ok_or(USAGE)?),
+            "--tolerance" => {
+                let value = args.next().ok_or(USAGE)?;
+                tolerance = Some(
+                    value
+                        .parse::<f64>()
+                        .map_err(|err| format!("Invalid tolerance '{}': {}", value, err))?,
+                );
+            }
+            "--trace" => trace_path = Some(args.next().ok_or(USAGE)?),
+            "--plate" => plate = Some(parse_length(&args.next().ok_or(USAGE)?)?),
+            "--height" => heights.push(parse_length(&args.next().ok_or(USAGE)?)?),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [pipeline_path, input_path, output_path] = positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+
+    let mut pipeline = Pipeline::load(std::path::Path::new(pipeline_path))?;
+    for (name, value) in params {
+        pipeline.set_param(&name, value);
+    }
+
+    if output_groups.is_empty() {
+        output_groups.push("main".to_string());
+    }
+
+    let outputs: Vec<&str> = output_groups.iter().map(String::as_str).collect();
+    let issues = pipeline.validate_with_outputs(&outputs);
+    for issue in &issues {
+        eprintln!("{}", issue);
+    }
+    if issues.iter().any(Issue::is_error) {
+        return Err(format!("'{}' is not a valid pipeline.", pipeline_path));
+    }
+
+    let input: Box<std::path::Path> = Box::from(std::path::Path::new(input_path));
+    let mut data: Data = if input_path.ends_with(".dxf") {
+        Data::from_dxf(&input, tolerance.unwrap_or(0.0001))?
+    } else if [".nc", ".ngc", ".gcode", ".tap"]
+        .iter()
+        .any(|extension| input_path.ends_with(extension))
+    {
+        Data::from_gcode(&input, tolerance.unwrap_or(0.0001))?
+    } else if input_path.ends_with(".geojson") || input_path.ends_with(".wkt") {
+        let text = std::fs::read_to_string(&input)
+            .map_err(|err| format!("Could not read '{}': {}", input_path, err))?;
+        if input_path.ends_with(".wkt") {
+            Data::from_wkt(&text)?
+        } else {
+            Data::from_geojson(&text)?
+        }
+    } else {
+        match tolerance {
+            Some(tolerance) => (input, tolerance).into(),
+            None => input.into(),
+        }
+    };
+
+    if trace_path.is_some() {
+        data.start_trace();
+    }
+    let result = pipeline.run(&mut data);
+
+    // Written even when the pipeline failed, since that's when it helps most
+    if let (Some(trace_path), Some(trace)) = (&trace_path, data.take_trace()) {
+        std::fs::write(trace_path, trace.to_json()?)
+            .map_err(|err| format!("Could not write '{}': {}", trace_path, err))?;
+    }
+    result?;
+
+    let polygons = {
+        let groups = data.groups.lock().unwrap();
+        let shapes = data.shapes.lock().unwrap();
+
+        let mut polygons = Vec::new();
+        for name in &output_groups {
+            let Some(group) = groups.get(name) else {
+                return Err(format!("Could not find '{}' in groups.", name));
+            };
+            for index in group.iter().flatten() {
+                polygons.push(shapes[*index].clone());
+            }
+        }
+        polygons
+    };
+
+    let mut file = std::fs::File::create(output_path)
+        .map_err(|err| format!("Could not create '{}': {}", output_path, err))?;
+    if output_path.ends_with(".png") || output_path.ends_with(".stl") {
+        let output = if output_path.ends_with(".png") {
+            groups_to_png(&data, &outputs, &RasterOptions::default())?
+        } else {
+            // Groups given a height stand that tall on the plate, the rest 1/32"
+            let mut options = match &plate {
+                Some((group, thickness)) => StlOptions::with_plate(group, *thickness),
+                None => StlOptions::default(),
+            };
+            for (group, height) in &heights {
+                let base = options.plate;
+                options.extrusions.insert(
+                    group.clone(),
+                    Extrusion::Prism {
+                        base,
+                        height: *height,
+                    },
+                );
+            }
+            groups_to_stl(&data, &outputs, &options)
+        };
+        return file
+            .write_all(&output)
+            .map_err(|err| format!("Could not write '{}': {}", output_path, err));
+    }
+    let output = if output_path.ends_with(".dxf") {
+        groups_to_dxf(&data, &outputs)
+    } else if output_path.ends_with(".pdf") {
+        groups_to_pdf(&data, &outputs, &PdfOptions::default())
+    } else if output_path.ends_with(".plt") || output_path.ends_with(".hpgl") {
+        groups_to_hpgl(&data, &outputs, &HpglOptions::default())
+    } else if output_path.ends_with(".geojson") {
+        data.to_geojson(&outputs)
+    } else if output_path.ends_with(".wkt") {
+        data.to_wkt(&outputs)
+    } else {
+        polygons_to_svg(&polygons)
+    };
+    file.write_all(output.as_bytes())
+        .map_err(|err| format!("Could not write '{}': {}", output_path, err))?;
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -1,27 +1,57 @@
 use boa_engine::{
-    Context, JsError, JsResult, JsValue, NativeFunction, js_string, object::ObjectInitializer,
+    Context, JsError, JsResult, JsString, JsValue, js_string, object::ObjectInitializer,
     property::Attribute,
 };
 use depth_tree::Tree;
 use geo::*;
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
 };
 
-use crate::Query;
+use crate::{
+    Expression, Macro, Observer, Query, QueryEnd, QueryStart, Shapes, Snapshot,
+    expression::function_source,
+    trace::{SharedTrace, traced},
+};
+
+/// Sub-groups of shape indexes, keyed by group name.
+pub type Groups = HashMap<String, Vec<Vec<usize>>>;
+
+/// Values stored by `Map`, keyed by group name, then attribute name, then sub-group index.
+pub type Attributes = HashMap<String, HashMap<String, Vec<serde_json::Value>>>;
 
 #[derive(Debug, Default)]
 pub struct Data {
-    pub shapes: Arc<Mutex<Vec<Polygon>>>,
+    pub shapes: Arc<Mutex<Shapes>>,
     pub depths: Arc<Mutex<Vec<usize>>>,
-    pub groups: Arc<Mutex<HashMap<String, Vec<Vec<usize>>>>>,
+    /// Shared with the `Snapshot`s taken since the last change, so change it through
+    /// `Arc::make_mut`. The same goes for `attributes`.
+    pub groups: Arc<Mutex<Arc<Groups>>>,
+    pub attributes: Arc<Mutex<Arc<Attributes>>>,
+    pub params: HashMap<String, serde_json::Value>,
+    pub macros: HashMap<String, Macro>,
+    /// Snapshots saved by `Checkpoint` queries.
+    pub checkpoints: HashMap<String, Snapshot>,
     pub context: Context,
+    /// Compiled expressions keyed by their function source, see `Data::compile`.
+    pub(crate) expressions: HashMap<String, Expression>,
+    /// Calls made to any compiled expression, shared with every `Expression`.
+    pub(crate) evaluations: Arc<AtomicUsize>,
+    /// What `Filter`, `GroupBy` and `Kerning` decided, see `Data::start_trace`.
+    pub(crate) trace: SharedTrace,
+    pub(crate) observer: Option<Box<dyn Observer>>,
+    /// How many queries `Data::run` is currently inside of.
+    pub(crate) query_depth: usize,
 }
 
 fn get_polygons(
-    shapes: &Arc<Mutex<Vec<Polygon>>>,
-    groups: &Arc<Mutex<HashMap<String, Vec<Vec<usize>>>>>,
+    shapes: &Arc<Mutex<Shapes>>,
+    groups: &Arc<Mutex<Arc<Groups>>>,
     args: &[JsValue],
 ) -> Vec<Polygon> {
     let shapes = shapes.lock().unwrap();
@@ -83,9 +113,7 @@ impl From<(Box<std::path::Path>, f64)> for Data {
     fn from(value: (Box<std::path::Path>, f64)) -> Self {
         let lines =
             MultiLineString::new(depth_tree::import_svg(&(*value.0), value.1 as f32).unwrap());
-        println!("DONE THIS");
         let mut lines = lines.simplify(value.1);
-        println!("Simplified");
 
         let (min_x, min_y) = lines.bounding_rect().unwrap().min().x_y();
         lines.translate_mut(-min_x, -min_y);
@@ -103,23 +131,153 @@ impl From<(Box<std::path::Path>, f64)> for Data {
 
 impl Data {
     pub fn query<T: Query>(&mut self, queries: Vec<T>) -> Result<(), String> {
-        let mut i = 0;
-        let n = queries.len();
         for mut query in queries {
-            println!("QUERY: {} out of {}", i, n);
-            i += 1;
-            query.query(self)?;
+            self.run(&mut query)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a single query, reporting it to the observer if there is one. Queries that run
+    /// other queries, like `LoopOver`, go through this too.
+    pub fn run<T: Query + ?Sized>(&mut self, query: &mut T) -> Result<(), String> {
+        self.clear_pending_trace();
+        if self.observer.is_none() {
+            return query.query(self);
+        }
+
+        let name = query.name();
+        let parameters = query.describe();
+        let depth = self.query_depth;
+        let group_sizes_before = self.group_sizes();
+        if let Some(observer) = &mut self.observer {
+            observer.query_start(&QueryStart {
+                name: name.clone(),
+                parameters: parameters.clone(),
+                depth,
+                group_sizes: group_sizes_before.clone(),
+            });
+        }
+
+        let evaluations = self.evaluations.load(Ordering::Relaxed);
+        let start = Instant::now();
+        self.query_depth += 1;
+        let result = query.query(self);
+        self.query_depth -= 1;
+        let elapsed = start.elapsed();
+
+        let group_sizes_after = self.group_sizes();
+        if let Some(observer) = &mut self.observer {
+            observer.query_end(&QueryEnd {
+                name,
+                parameters,
+                depth,
+                elapsed,
+                evaluations: self.evaluations.load(Ordering::Relaxed) - evaluations,
+                group_sizes_before,
+                group_sizes_after,
+                error: result.as_ref().err().cloned(),
+            });
+        }
+
+        result
+    }
+
+    /// Reports every query run from now on to `observer`.
+    pub fn set_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn take_observer(&mut self) -> Option<Box<dyn Observer>> {
+        self.observer.take()
+    }
+
+    /// The number of sub-groups in every group.
+    pub fn group_sizes(&self) -> HashMap<String, usize> {
+        let groups = self.groups.lock().unwrap();
+        groups
+            .iter()
+            .map(|(name, group)| (name.clone(), group.len()))
+            .collect()
+    }
+
+    /// Makes `definition` available to `Call` queries as `name`.
+    pub fn define_macro(&mut self, name: &str, definition: Macro) {
+        self.macros.insert(name.to_string(), definition);
+    }
+
+    /// Compiles `code` into a function of `params`, reusing the function if the same code was
+    /// compiled before so queries run inside loops don't parse it again.
+    pub fn compile(&mut self, code: &str, params: &[&str]) -> Result<Expression, String> {
+        let key = function_source(code, params);
+        if let Some(expression) = self.expressions.get(&key) {
+            return Ok(expression.clone());
         }
 
+        let mut expression = Expression::compile(code, params, &mut self.context)?;
+        expression.evaluations = self.evaluations.clone();
+        expression.trace = self.trace.clone();
+        self.expressions.insert(key, expression.clone());
+
+        Ok(expression)
+    }
+
+    /// Sets a pipeline parameter and exposes it to JS as a global named `name`.
+    pub fn set_param(&mut self, name: &str, value: serde_json::Value) -> Result<(), String> {
+        let js_value = JsValue::from_json(&value, &mut self.context)
+            .map_err(|err| format!("Could not convert param '{}': {}", name, err))?;
+        self.context
+            .register_global_property(JsString::from(name), js_value, Attribute::all())
+            .map_err(|err| format!("Could not set param '{}': {}", name, err))?;
+        self.params.insert(name.to_string(), value);
+
+        Ok(())
+    }
+
+    /// Removes a pipeline parameter and its JS global.
+    pub fn remove_param(&mut self, name: &str) -> Result<(), String> {
+        self.context
+            .global_object()
+            .delete_property_or_throw(JsString::from(name), &mut self.context)
+            .map_err(|err| format!("Could not remove param '{}': {}", name, err))?;
+        self.params.remove(name);
+
         Ok(())
     }
 
+    /// Rebuilds the containment depth of the given shapes from their current geometry.
+    ///
+    /// Only the listed shapes take part in the tree, so copies made by `Transformation` or
+    /// `Kerning` don't nest inside the originals they were made from.
+    pub fn recompute_depths(&self, indexes: &[usize]) {
+        let value: Vec<(usize, Polygon)> = {
+            let shapes = self.shapes.lock().unwrap();
+            indexes
+                .iter()
+                .map(|index| (*index, shapes[*index].clone()))
+                .collect()
+        };
+        let tree: Tree<(usize, Polygon)> = Tree::from_polygon_id(value);
+
+        let mut depths = self.depths.lock().unwrap();
+        for (depth, polygon) in tree.iter() {
+            depths[polygon.0] = depth;
+        }
+    }
+
+    /// Sets the group `name`, dropping the attributes `Map` stored for its old sub-groups.
+    pub fn set_group(&self, name: &str, group: Vec<Vec<usize>>) {
+        Arc::make_mut(&mut self.groups.lock().unwrap()).insert(name.to_string(), group);
+        let mut attributes = self.attributes.lock().unwrap();
+        if attributes.contains_key(name) {
+            Arc::make_mut(&mut attributes).remove(name);
+        }
+    }
+
     pub fn from_respect_indexes(value: Vec<Polygon>) -> (Self, Vec<usize>) {
         let value: Vec<(usize, Polygon)> = value.into_iter().enumerate().collect();
         let len = value.len();
-        println!("Len: {}", len);
         let tree: Tree<(usize, Polygon)> = Tree::from_polygon_id(value);
-        println!("Built Tree");
 
         let mut shapes = Vec::with_capacity(len);
         let mut depths = Vec::with_capacity(len);
@@ -131,27 +289,46 @@ impl Data {
             depths.push(depth);
         }
 
-        let shapes = Arc::new(Mutex::new(shapes));
+        let groups = vec![("main".into(), (0..len).map(|x| vec![x]).collect())]
+            .into_iter()
+            .collect::<HashMap<String, Vec<Vec<usize>>>>();
+
+        (Self::from_parts(shapes, depths, groups), indexes)
+    }
+
+    /// Builds a `Data` around existing shapes, depths and groups with a fresh JS context.
+    pub fn from_parts(
+        shapes: Vec<Polygon>,
+        depths: Vec<usize>,
+        groups: HashMap<String, Vec<Vec<usize>>>,
+    ) -> Self {
+        let shapes = Arc::new(Mutex::new(Shapes::from(shapes)));
         let depths = Arc::new(Mutex::new(depths));
-        let groups = Arc::new(Mutex::new(
-            vec![("main".into(), (0..len).map(|x| vec![x]).collect())]
-                .into_iter()
-                .collect::<HashMap<String, Vec<Vec<usize>>>>(),
-        ));
+        let groups = Arc::new(Mutex::new(Arc::new(groups)));
+        let attributes: Arc<Mutex<Arc<Attributes>>> = Arc::default();
 
         let mut context = Context::default();
+        let trace = SharedTrace::default();
         unsafe {
             {
                 let depths = depths.clone();
                 context.register_global_callable(
                     "depth".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "depth",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let depths = depths.lock().unwrap();
                             match args.first() {
                                 Some(JsValue::Integer(index)) => {
-                                    JsResult::Ok(JsValue::new(depths[*index as usize]))
+                                    if let Some(depth) = depths.get(*index as usize) {
+                                        JsResult::Ok(JsValue::new(*depth))
+                                    } else {
+                                        JsResult::Err(JsError::from_opaque(
+                                            js_string!("Index out of bounds").into(),
+                                        ))
+                                    }
                                 }
                                 _ => JsResult::Ok(JsValue::new(0.0)),
                             }
@@ -166,7 +343,9 @@ impl Data {
                 context.register_global_callable(
                     "area".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "area",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let shapes = shapes.lock().unwrap();
                             let groups = groups.lock().unwrap();
@@ -212,7 +391,9 @@ impl Data {
                 context.register_global_callable(
                     "group_index".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "group_index",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let groups = groups.lock().unwrap();
                             let mut iter = args.iter();
@@ -258,7 +439,9 @@ impl Data {
                 context.register_global_callable(
                     "frame".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "frame",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let shapes = shapes.lock().unwrap();
                             let groups = groups.lock().unwrap();
@@ -416,7 +599,9 @@ impl Data {
                 context.register_global_callable(
                     "len".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "len",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let shapes = shapes.lock().unwrap();
                             let groups = groups.lock().unwrap();
@@ -442,9 +627,14 @@ impl Data {
                                 ) => JsResult::Ok(JsValue::new(
                                     groups[&name.to_std_string_lossy()][*index as usize].len(),
                                 )),
-                                (Some(JsValue::String(name)), None, None) => JsResult::Ok(
-                                    JsValue::new(groups[&name.to_std_string_lossy()].len()),
-                                ),
+                                // A group that was never set has no sub-groups
+                                (Some(JsValue::String(name)), None, None) => {
+                                    JsResult::Ok(JsValue::new(
+                                        groups
+                                            .get(&name.to_std_string_lossy())
+                                            .map_or(0, |group| group.len()),
+                                    ))
+                                }
                                 _ => JsResult::Ok(JsValue::new(0.0)),
                             }
                         },
@@ -458,7 +648,9 @@ impl Data {
                 context.register_global_callable(
                     "center".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "center",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let polygons = get_polygons(&shapes, &groups, args);
                             let points = get_points(&polygons);
@@ -484,7 +676,9 @@ impl Data {
                 context.register_global_callable(
                     "circle_metrics".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "circle_metrics",
                         move |this: &JsValue, args: &[JsValue], context: &mut Context| {
                             let polygons = get_polygons(&shapes, &groups, &args);
                             let points = get_points(&polygons);
@@ -530,7 +724,9 @@ impl Data {
                 context.register_global_callable(
                     "distance".into(),
                     0,
-                    NativeFunction::from_closure(
+                    traced(
+                        &trace,
+                        "distance",
                         move |_this: &JsValue, args: &[JsValue], _context: &mut Context| {
                             let mut index = 0;
                             for arg in args {
@@ -562,17 +758,59 @@ impl Data {
                     ),
                 );
             }
+
+            {
+                let attributes = attributes.clone();
+                context.register_global_callable(
+                    "attr".into(),
+                    0,
+                    traced(
+                        &trace,
+                        "attr",
+                        move |_this: &JsValue, args: &[JsValue], context: &mut Context| {
+                            let value = {
+                                let attributes = attributes.lock().unwrap();
+                                let mut iter = args.iter();
+                                match (iter.next(), iter.next(), iter.next()) {
+                                    (
+                                        Some(JsValue::String(name)),
+                                        Some(JsValue::Integer(index)),
+                                        Some(JsValue::String(attribute)),
+                                    ) => attributes
+                                        .get(&name.to_std_string_lossy())
+                                        .and_then(|group| {
+                                            group.get(&attribute.to_std_string_lossy())
+                                        })
+                                        .and_then(|values| values.get(*index as usize))
+                                        .cloned(),
+                                    _ => None,
+                                }
+                            };
+
+                            match value {
+                                Some(value) => JsValue::from_json(&value, context),
+                                None => JsResult::Ok(JsValue::undefined()),
+                            }
+                        },
+                    ),
+                );
+            }
         }
 
-        println!("Build Data");
-        (
-            Self {
-                shapes,
-                depths,
-                groups,
-                context,
-            },
-            indexes,
-        )
+        Self {
+            shapes,
+            depths,
+            groups,
+            attributes,
+            params: HashMap::new(),
+            macros: HashMap::new(),
+            checkpoints: HashMap::new(),
+            context,
+            expressions: HashMap::new(),
+            evaluations: Arc::default(),
+            trace,
+            observer: None,
+            query_depth: 0,
+        }
     }
 }
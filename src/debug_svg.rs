@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Coord, MapCoords, MultiPolygon, Polygon, Rect, coord};
+use serde_json::Value;
+
+use crate::{Data, Shapes, Trace, save_svg::polygon_to_svg_path};
+
+/// A color for the `n`th sub-group, spread around the hue circle so neighbours differ.
+fn color(n: usize) -> String {
+    format!("hsl({:.0}, 75%, 42%)", (n as f64 * 137.508) % 360.0)
+}
+
+/// The shapes a builtin such as `distance` was called with, like `get_polygons` does with
+/// the JS values.
+fn resolve(
+    args: &[Value],
+    shapes: &Shapes,
+    groups: &HashMap<String, Vec<Vec<usize>>>,
+) -> Vec<Polygon> {
+    let index = |value: &Value| value.as_u64().map(|value| value as usize);
+    let indexes: Vec<usize> = match args {
+        [value, ..] if value.is_number() => index(value).into_iter().collect(),
+        [Value::String(name), i, j] => groups
+            .get(name)
+            .zip(index(i).zip(index(j)))
+            .and_then(|(group, (i, j))| group.get(i)?.get(j).copied())
+            .into_iter()
+            .collect(),
+        [Value::String(name), i] => groups
+            .get(name)
+            .zip(index(i))
+            .and_then(|(group, i)| group.get(i).cloned())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    indexes
+        .into_iter()
+        .filter_map(|index| shapes.get(index).cloned())
+        .collect()
+}
+
+fn center(polygons: &[Polygon]) -> Option<Coord> {
+    MultiPolygon::new(polygons.to_vec())
+        .bounding_rect()
+        .map(|rect| rect.center())
+}
+
+/// Draws the sub-groups of `groups` for debugging a pipeline.
+///
+/// Every sub-group gets its own color with its frame, its center and its index, and every
+/// shape is labelled with its index. While `Data::start_trace` is on, the moves made by a
+/// `Kerning` that set one of `groups` are drawn as arrows, and the `distance` calls that made
+/// a traced query keep or group an element are drawn as lines labelled with the distance.
+pub fn render_debug_svg(data: &Data, groups: &[&str]) -> String {
+    let shapes = data.shapes.lock().unwrap().clone();
+    let data_groups = data.groups.lock().unwrap().clone();
+    let trace: Option<Trace> = data.trace.lock().unwrap().clone();
+
+    let drawn: Vec<(&str, &Vec<Vec<usize>>)> = groups
+        .iter()
+        .filter_map(|name| data_groups.get(*name).map(|group| (*name, group)))
+        .collect();
+
+    let Some(frame) = MultiPolygon::new(
+        drawn
+            .iter()
+            .flat_map(|(_, group)| group.iter().flatten())
+            .filter_map(|index| shapes.get(*index).cloned())
+            .collect(),
+    )
+    .bounding_rect() else {
+        return r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#.to_string();
+    };
+
+    // Same orientation as `polygons_to_svg`, y pointing up
+    let margin = frame.width().max(frame.height()) * 0.05;
+    let frame = Rect::new(
+        coord! {x: frame.min().x - margin, y: frame.min().y - margin},
+        coord! {x: frame.max().x + margin, y: frame.max().y + margin},
+    );
+    let map = |c: Coord| coord! {x: c.x - frame.min().x, y: frame.max().y - c.y};
+    let unit = frame.width().max(frame.height()) / 400.0;
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}in" height="{}in" viewBox="0 0 {} {}">"#,
+        frame.width(),
+        frame.height(),
+        frame.width(),
+        frame.height(),
+    );
+    svg += &format!(
+        r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z"/></marker></defs><g font-family="monospace" font-size="{}">"#,
+        unit * 6.0
+    );
+
+    let mut n = 0;
+    for (name, group) in &drawn {
+        for (index, sub_group) in group.iter().enumerate() {
+            let color = color(n);
+            n += 1;
+
+            let polygons: Vec<Polygon> = sub_group
+                .iter()
+                .filter_map(|index| shapes.get(*index).cloned())
+                .collect();
+            for (shape_index, polygon) in sub_group.iter().zip(&polygons) {
+                svg += &format!(
+                    r#"<path d="{}" fill="{}" fill-opacity="0.15" fill-rule="evenodd" stroke="{}" stroke-width="{}"/>"#,
+                    polygon_to_svg_path(&polygon.map_coords(map)),
+                    color,
+                    color,
+                    unit * 0.5
+                );
+                if let Some(rect) = polygon.bounding_rect() {
+                    let at = map(rect.center());
+                    svg += &format!(
+                        r#"<text x="{}" y="{}" fill="{}" text-anchor="middle">{}</text>"#,
+                        at.x, at.y, color, shape_index
+                    );
+                }
+            }
+
+            let Some(rect) = MultiPolygon::new(polygons).bounding_rect() else {
+                continue;
+            };
+            let top_left = map(coord! {x: rect.min().x, y: rect.max().y});
+            let center = map(rect.center());
+            svg += &format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-width="{}" stroke-dasharray="{} {}"/>"#,
+                top_left.x,
+                top_left.y,
+                rect.width(),
+                rect.height(),
+                color,
+                unit * 0.5,
+                unit * 2.0,
+                unit * 2.0
+            );
+            svg += &format!(
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                center.x,
+                center.y,
+                unit * 1.5,
+                color
+            );
+            svg += &format!(
+                r#"<text x="{}" y="{}" fill="{}">{}[{}]</text>"#,
+                top_left.x,
+                top_left.y - unit,
+                color,
+                name,
+                index
+            );
+        }
+    }
+
+    for query in trace.iter().flat_map(|trace| &trace.queries) {
+        if !groups.contains(&query.set_group.as_str()) {
+            continue;
+        }
+
+        for element in &query.elements {
+            if let (Some(kerning), Some(joined)) = (&element.kerning, element.joined) {
+                let Some(sub_group) = data_groups
+                    .get(&query.set_group)
+                    .and_then(|group| group.get(joined))
+                else {
+                    continue;
+                };
+                for (shape_index, offset) in sub_group.iter().zip(&kerning.offsets) {
+                    if offset[0].abs() + offset[1].abs() < f64::EPSILON {
+                        continue;
+                    }
+                    let Some(to) = shapes
+                        .get(*shape_index)
+                        .and_then(|shape| shape.bounding_rect())
+                    else {
+                        continue;
+                    };
+                    let to = to.center();
+                    let from = map(coord! {x: to.x - offset[0], y: to.y - offset[1]});
+                    let to = map(to);
+                    svg += &format!(
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black" stroke-width="{}" marker-end="url(#arrow)"/>"#,
+                        from.x,
+                        from.y,
+                        to.x,
+                        to.y,
+                        unit * 0.5
+                    );
+                }
+            }
+
+            for evaluation in &element.evaluations {
+                if evaluation.result != Value::Bool(true) {
+                    continue;
+                }
+                for call in evaluation
+                    .builtins
+                    .iter()
+                    .filter(|call| call.name == "distance")
+                {
+                    // The second shape starts at the first group name after the first argument
+                    let split = call
+                        .args
+                        .iter()
+                        .skip(1)
+                        .position(Value::is_string)
+                        .map_or(call.args.len(), |position| position + 1);
+                    let (first, second) = call.args.split_at(split);
+                    let (Some(first), Some(second)) = (
+                        center(&resolve(first, &shapes, &data_groups)),
+                        center(&resolve(second, &shapes, &data_groups)),
+                    ) else {
+                        continue;
+                    };
+                    let (first, second) = (map(first), map(second));
+                    svg += &format!(
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="gray" stroke-width="{}" stroke-dasharray="{} {}"/>"#,
+                        first.x,
+                        first.y,
+                        second.x,
+                        second.y,
+                        unit * 0.5,
+                        unit,
+                        unit
+                    );
+                    if let Some(distance) = call.result.as_f64() {
+                        svg += &format!(
+                            r#"<text x="{}" y="{}" fill="gray" text-anchor="middle">{:.3}</text>"#,
+                            (first.x + second.x) / 2.0,
+                            (first.y + second.y) / 2.0,
+                            distance
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    svg += "</g></svg>";
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![
+            polygon![
+                (x: 0.0, y: 0.0),
+                (x: 1.0, y: 0.0),
+                (x: 1.0, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            polygon![
+                (x: 1.5, y: 0.0),
+                (x: 2.5, y: 0.0),
+                (x: 2.5, y: 1.0),
+                (x: 1.5, y: 1.0),
+            ],
+            polygon![
+                (x: 9.0, y: 0.0),
+                (x: 10.0, y: 0.0),
+                (x: 10.0, y: 1.0),
+                (x: 9.0, y: 1.0),
+            ],
+        ]);
+        data.start_trace();
+
+        let mut groupby = GroupBy {
+            set_group: "words".into(),
+            get_group: "main".into(),
+            code: "distance('main', i, 'words', j) < 1".into(),
+        };
+        if let Err(err) = data.run(&mut groupby) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        assert_eq!(data.groups.lock().unwrap()["words"].len(), 2);
+
+        let svg = render_debug_svg(&data, &["words"]);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>"));
+        assert_eq!(svg.matches("fill-opacity").count(), 3);
+        assert_eq!(svg.matches("<rect").count(), 2);
+        assert_eq!(svg.matches("<circle").count(), 2);
+        assert!(svg.contains(">words[1]</text>"));
+        // Only the pair that was grouped gets a distance line
+        assert_eq!(svg.matches("stroke=\"gray\"").count(), 1);
+    }
+}
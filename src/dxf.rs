@@ -0,0 +1,460 @@
+use std::f64::consts::TAU;
+
+use geo::{Coord, Polygon, coord};
+
+use crate::{
+    Data,
+    flatten::{arc, bulge, chain, ellipse, ring_to_polygon, spline},
+};
+
+/// One entity of the ENTITIES section with its group codes in file order.
+#[derive(Debug)]
+struct Entity {
+    kind: String,
+    values: Vec<(i32, String)>,
+}
+
+impl Entity {
+    fn number(&self, code: i32) -> Option<f64> {
+        self.values
+            .iter()
+            .find(|(value_code, _)| *value_code == code)
+            .and_then(|(_, value)| value.parse().ok())
+    }
+
+    fn numbers(&self, code: i32) -> Vec<f64> {
+        self.values
+            .iter()
+            .filter(|(value_code, _)| *value_code == code)
+            .filter_map(|(_, value)| value.parse().ok())
+            .collect()
+    }
+
+    fn flags(&self) -> i64 {
+        self.number(70).unwrap_or(0.0) as i64
+    }
+
+    fn point(&self, x: i32, y: i32) -> Coord {
+        coord! {x: self.number(x).unwrap_or(0.0), y: self.number(y).unwrap_or(0.0)}
+    }
+
+    /// The points given by pairs of `x` and `y` codes, in order.
+    fn points(&self, x: i32, y: i32) -> Vec<Coord> {
+        let mut points: Vec<Coord> = Vec::new();
+        for (code, value) in &self.values {
+            let value = value.parse().unwrap_or(0.0);
+            if *code == x {
+                points.push(coord! {x: value, y: 0.0});
+            } else if *code == y {
+                if let Some(point) = points.last_mut() {
+                    point.y = value;
+                }
+            }
+        }
+        points
+    }
+
+    /// Entities drawn in an object coordinate system facing down have their x axis flipped.
+    fn mirror(&self, points: &mut [Coord]) {
+        if self.number(230).unwrap_or(1.0) < 0.0 {
+            for point in points {
+                point.x = -point.x;
+            }
+        }
+    }
+}
+
+fn pairs(dxf: &str) -> Result<Vec<(i32, String)>, String> {
+    let lines: Vec<&str> = dxf.lines().collect();
+    lines
+        .chunks(2)
+        .enumerate()
+        .filter(|(_, pair)| pair.len() == 2)
+        .map(|(index, pair)| {
+            let code = pair[0].trim().parse::<i32>().map_err(|_| {
+                format!(
+                    "Invalid group code '{}' on line {}.",
+                    pair[0].trim(),
+                    index * 2 + 1
+                )
+            })?;
+            Ok((code, pair[1].trim().to_string()))
+        })
+        .collect()
+}
+
+fn entities(dxf: &str) -> Result<Vec<Entity>, String> {
+    let pairs = pairs(dxf)?;
+
+    let Some(start) = pairs
+        .windows(2)
+        .position(|pair| pair[0] == (0, "SECTION".into()) && pair[1] == (2, "ENTITIES".into()))
+    else {
+        return Err("Could not find the ENTITIES section.".to_string());
+    };
+
+    let mut entities: Vec<Entity> = Vec::new();
+    for (code, value) in pairs.into_iter().skip(start + 2) {
+        if code == 0 {
+            if value == "ENDSEC" {
+                break;
+            }
+            entities.push(Entity {
+                kind: value,
+                values: Vec::new(),
+            });
+        } else if let Some(entity) = entities.last_mut() {
+            entity.values.push((code, value));
+        }
+    }
+
+    Ok(entities)
+}
+
+/// The points of a polyline made of `vertices` and the bulge after each of them.
+fn polyline(vertices: &[(Coord, f64)], closed: bool, tolerance: f64) -> Vec<Coord> {
+    let mut points: Vec<Coord> = vertices.iter().take(1).map(|vertex| vertex.0).collect();
+    let segments = if closed {
+        vertices.len()
+    } else {
+        vertices.len().saturating_sub(1)
+    };
+    for (index, (from, amount)) in vertices.iter().enumerate().take(segments) {
+        let to = vertices[(index + 1) % vertices.len()].0;
+        points.extend(bulge(*from, to, *amount, tolerance).into_iter().skip(1));
+    }
+    points
+}
+
+/// Reads the outlines of a DXF drawing.
+///
+/// LWPOLYLINE, POLYLINE, LINE, ARC, CIRCLE, ELLIPSE and SPLINE entities are flattened so no
+/// point is further than `tolerance` from the curve. Open pieces whose ends meet are chained
+/// into closed outlines and pieces that stay open are left out.
+pub fn dxf_to_polygons(dxf: &str, tolerance: f64) -> Result<Vec<Polygon>, String> {
+    let entities = entities(dxf)?;
+
+    let mut rings = Vec::new();
+    let mut paths = Vec::new();
+
+    let mut index = 0;
+    while index < entities.len() {
+        let entity = &entities[index];
+        index += 1;
+
+        let (mut points, closed) = match entity.kind.as_str() {
+            "LWPOLYLINE" => {
+                let mut vertices: Vec<(Coord, f64)> = Vec::new();
+                for (code, value) in &entity.values {
+                    let value = value.parse().unwrap_or(0.0);
+                    match code {
+                        10 => vertices.push((coord! {x: value, y: 0.0}, 0.0)),
+                        20 => {
+                            if let Some(vertex) = vertices.last_mut() {
+                                vertex.0.y = value;
+                            }
+                        }
+                        42 => {
+                            if let Some(vertex) = vertices.last_mut() {
+                                vertex.1 = value;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let closed = entity.flags() & 1 != 0;
+                (polyline(&vertices, closed, tolerance), closed)
+            }
+            "POLYLINE" => {
+                let mut vertices = Vec::new();
+                while index < entities.len() && entities[index].kind == "VERTEX" {
+                    let vertex = &entities[index];
+                    vertices.push((vertex.point(10, 20), vertex.number(42).unwrap_or(0.0)));
+                    index += 1;
+                }
+                if index < entities.len() && entities[index].kind == "SEQEND" {
+                    index += 1;
+                }
+
+                // Meshes and polyface meshes aren't outlines
+                if entity.flags() & (16 | 64) != 0 {
+                    continue;
+                }
+                let closed = entity.flags() & 1 != 0;
+                (polyline(&vertices, closed, tolerance), closed)
+            }
+            "LINE" => (vec![entity.point(10, 20), entity.point(11, 21)], false),
+            "ARC" => {
+                let start = entity.number(50).unwrap_or(0.0).to_radians();
+                let mut sweep = entity.number(51).unwrap_or(0.0).to_radians() - start;
+                if sweep <= 0.0 {
+                    sweep += TAU;
+                }
+                let radius = entity.number(40).unwrap_or(0.0);
+                (
+                    arc(entity.point(10, 20), radius, start, sweep, tolerance),
+                    false,
+                )
+            }
+            "CIRCLE" => {
+                let radius = entity.number(40).unwrap_or(0.0);
+                (arc(entity.point(10, 20), radius, 0.0, TAU, tolerance), true)
+            }
+            "ELLIPSE" => {
+                let start = entity.number(41).unwrap_or(0.0);
+                let end = entity.number(42).unwrap_or(TAU);
+                let closed = ((end - start).abs() - TAU).abs() < 1e-9;
+                (
+                    ellipse(
+                        entity.point(10, 20),
+                        entity.point(11, 21),
+                        entity.number(40).unwrap_or(1.0),
+                        start,
+                        end,
+                        tolerance,
+                    ),
+                    closed,
+                )
+            }
+            "SPLINE" => {
+                let control_points = entity.points(10, 20);
+                let points = if control_points.is_empty() {
+                    entity.points(11, 21)
+                } else {
+                    spline(
+                        entity.number(71).unwrap_or(3.0) as usize,
+                        &control_points,
+                        &entity.numbers(40),
+                        &entity.numbers(41),
+                        tolerance,
+                    )
+                };
+                (points, entity.flags() & 1 != 0)
+            }
+            _ => continue,
+        };
+
+        if matches!(
+            entity.kind.as_str(),
+            "LWPOLYLINE" | "POLYLINE" | "ARC" | "CIRCLE"
+        ) {
+            entity.mirror(&mut points);
+        }
+        if closed {
+            rings.push(points);
+        } else {
+            paths.push(points);
+        }
+    }
+
+    rings.extend(chain(paths, tolerance));
+
+    Ok(rings
+        .into_iter()
+        .filter_map(|ring| ring_to_polygon(ring, tolerance))
+        .collect())
+}
+
+/// Layer names can't hold some characters, so they're replaced.
+fn layer_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '<' | '>' | '/' | '\\' | '"' | ':' | ';' | '?' | '*' | '|' | '=' | '`' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn write_dxf(layers: &[(String, Vec<Polygon>)]) -> String {
+    let mut dxf = String::new();
+    let mut pair = |code: i32, value: &str| {
+        dxf += &format!("{}\n{}\n", code, value);
+    };
+
+    pair(0, "SECTION");
+    pair(2, "TABLES");
+    pair(0, "TABLE");
+    pair(2, "LAYER");
+    pair(70, &layers.len().to_string());
+    for (index, (name, _)) in layers.iter().enumerate() {
+        pair(0, "LAYER");
+        pair(2, name);
+        pair(70, "0");
+        pair(62, &(index % 7 + 1).to_string());
+        pair(6, "CONTINUOUS");
+    }
+    pair(0, "ENDTAB");
+    pair(0, "ENDSEC");
+
+    pair(0, "SECTION");
+    pair(2, "ENTITIES");
+    for (name, polygons) in layers {
+        for polygon in polygons {
+            for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+                pair(0, "POLYLINE");
+                pair(8, name);
+                pair(66, "1");
+                pair(70, "1");
+                pair(10, "0");
+                pair(20, "0");
+                pair(30, "0");
+
+                // The ring repeats its first point at the end, a closed polyline doesn't
+                let count = ring.0.len().saturating_sub(1).max(1);
+                for point in ring.0.iter().take(count) {
+                    pair(0, "VERTEX");
+                    pair(8, name);
+                    pair(10, &point.x.to_string());
+                    pair(20, &point.y.to_string());
+                    pair(30, "0");
+                }
+                pair(0, "SEQEND");
+                pair(8, name);
+            }
+        }
+    }
+    pair(0, "ENDSEC");
+    pair(0, "EOF");
+
+    dxf
+}
+
+/// Writes polygons as closed polylines on layer `0`.
+pub fn polygons_to_dxf(polygons: &[Polygon]) -> String {
+    write_dxf(&[("0".to_string(), polygons.to_vec())])
+}
+
+/// Writes every shape of `groups` with each group on a layer of its own name.
+pub fn groups_to_dxf(data: &Data, groups: &[&str]) -> String {
+    let shapes = data.shapes.lock().unwrap();
+    let data_groups = data.groups.lock().unwrap();
+
+    let layers: Vec<(String, Vec<Polygon>)> = groups
+        .iter()
+        .filter_map(|name| {
+            let group = data_groups.get(*name)?;
+            let polygons = group
+                .iter()
+                .flatten()
+                .map(|index| shapes[*index].clone())
+                .collect();
+            Some((layer_name(name), polygons))
+        })
+        .collect();
+
+    write_dxf(&layers)
+}
+
+impl Data {
+    /// Loads the outlines of a DXF file, see `dxf_to_polygons`.
+    pub fn from_dxf(path: &std::path::Path, tolerance: f64) -> Result<Self, String> {
+        let dxf = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+        Ok(dxf_to_polygons(&dxf, tolerance)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, BoundingRect};
+
+    use crate::*;
+
+    fn dxf(pairs: &[(i32, &str)]) -> String {
+        pairs
+            .iter()
+            .map(|(code, value)| format!("{}\n{}\n", code, value))
+            .collect()
+    }
+
+    #[test]
+    fn it_works() {
+        let text = dxf(&[
+            (0, "SECTION"),
+            (2, "ENTITIES"),
+            // A 4 by 2 rectangle with a half circle on its right side
+            (0, "LWPOLYLINE"),
+            (90, "4"),
+            (70, "1"),
+            (10, "0"),
+            (20, "0"),
+            (10, "4"),
+            (20, "0"),
+            (42, "1"),
+            (10, "4"),
+            (20, "2"),
+            (10, "0"),
+            (20, "2"),
+            // A triangle made of lines, one of them reversed
+            (0, "LINE"),
+            (10, "10"),
+            (20, "0"),
+            (11, "12"),
+            (21, "0"),
+            (0, "LINE"),
+            (10, "11"),
+            (20, "1"),
+            (11, "12"),
+            (21, "0"),
+            (0, "LINE"),
+            (10, "11"),
+            (20, "1"),
+            (11, "10"),
+            (21, "0"),
+            (0, "CIRCLE"),
+            (10, "1"),
+            (20, "1"),
+            (40, "0.5"),
+            // Left open, so it isn't an outline
+            (0, "ARC"),
+            (10, "20"),
+            (20, "0"),
+            (40, "1"),
+            (50, "0"),
+            (51, "90"),
+            (0, "ENDSEC"),
+            (0, "EOF"),
+        ]);
+
+        let polygons = match dxf_to_polygons(&text, 0.001) {
+            Ok(polygons) => polygons,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        assert_eq!(polygons.len(), 3);
+
+        let mut areas: Vec<f64> = polygons
+            .iter()
+            .map(|polygon| polygon.unsigned_area())
+            .collect();
+        areas.sort_by(|l, r| l.partial_cmp(r).unwrap());
+        assert!((areas[0] - std::f64::consts::PI * 0.25).abs() < 0.01);
+        assert!((areas[1] - 1.0).abs() < 1e-9);
+        assert!((areas[2] - (8.0 + std::f64::consts::PI / 2.0)).abs() < 0.01);
+
+        let rectangle = polygons
+            .iter()
+            .find(|polygon| polygon.unsigned_area() > 5.0)
+            .unwrap();
+        assert!((rectangle.bounding_rect().unwrap().max().x - 5.0).abs() < 0.01);
+
+        // The circle is inside the rectangle
+        let data = Data::from(polygons);
+        assert_eq!(
+            data.depths
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|depth| **depth > 0)
+                .count(),
+            1
+        );
+
+        let written = groups_to_dxf(&data, &["main"]);
+        assert!(written.contains("\n8\nmain\n"));
+        assert_eq!(dxf_to_polygons(&written, 0.001).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,307 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use boa_engine::{Context, JsResult, JsValue, Source, object::builtins::JsFunction};
+
+use crate::trace::{SharedTrace, evaluate};
+
+/// A JS expression compiled once into a function of named parameters.
+///
+/// The code may be a list of `;` separated statements, in which case the value of the last
+/// one is returned. Top level assignments such as `my_frame = frame('group', i);` declare a
+/// local instead of a global. The code runs in strict mode, so any other assignment to an
+/// undeclared name throws instead of leaking into the next evaluation.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    function: JsFunction,
+    code: String,
+    /// Counts calls, shared with `Data` when compiled through `Data::compile`.
+    pub(crate) evaluations: Arc<AtomicUsize>,
+    /// Records calls while `Data::start_trace` is on, shared like `evaluations`.
+    pub(crate) trace: SharedTrace,
+}
+
+impl Expression {
+    pub fn compile(code: &str, params: &[&str], context: &mut Context) -> Result<Self, String> {
+        let source = function_source(code, params);
+        let value = context
+            .eval(Source::from_bytes(&source))
+            .map_err(|err| format!("Could not compile '{}': {}", code, err))?;
+
+        let function = value
+            .as_object()
+            .cloned()
+            .and_then(JsFunction::from_object)
+            .ok_or_else(|| format!("Could not compile '{}'.", code))?;
+
+        Ok(Self {
+            function,
+            code: code.to_string(),
+            evaluations: Arc::default(),
+            trace: SharedTrace::default(),
+        })
+    }
+
+    pub fn call(&self, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        evaluate(&self.trace, &self.code, args, context, |context| {
+            self.function.call(&JsValue::undefined(), args, context)
+        })
+    }
+}
+
+const STATEMENT_KEYWORDS: [&str; 14] = [
+    "let", "const", "var", "function", "class", "if", "for", "while", "do", "switch", "try",
+    "return", "throw", "{",
+];
+
+/// Wraps `code` into the source of a function expression taking `params`.
+pub(crate) fn function_source(code: &str, params: &[&str]) -> String {
+    let statements = split_statements(code);
+
+    let mut declared: Vec<&str> = params.to_vec();
+    let mut locals = Vec::new();
+    for statement in &statements {
+        if let Some(name) = declaration_target(statement) {
+            declared.push(name);
+        } else {
+            for name in assignment_targets(statement) {
+                if !declared.contains(&name) {
+                    declared.push(name);
+                    locals.push(name);
+                }
+            }
+        }
+    }
+
+    let mut body = String::new();
+    for local in locals {
+        body += &format!("let {};\n", local);
+    }
+    for (index, statement) in statements.iter().enumerate() {
+        let is_last = index + 1 == statements.len();
+        if is_last && !starts_with_keyword(statement) {
+            body += &format!("return (\n{}\n);\n", statement);
+        } else {
+            body += &format!("{}\n;\n", statement);
+        }
+    }
+
+    // The extra block lets the code shadow a parameter with its own `let`
+    format!(
+        "(function ({}) {{\n'use strict';\n{{\n{}}}\n}})",
+        params.join(", "),
+        body
+    )
+}
+
+/// Splits code on the `;` that aren't inside brackets, strings or comments.
+fn split_statements(code: &str) -> Vec<&str> {
+    let bytes = code.as_bytes();
+    let mut statements = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            quote @ (b'\'' | b'"' | b'`') => {
+                index += 1;
+                while index < bytes.len() && bytes[index] != quote {
+                    if bytes[index] == b'\\' {
+                        index += 1;
+                    }
+                    index += 1;
+                }
+            }
+            b'/' if bytes.get(index + 1) == Some(&b'/') => {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    index += 1;
+                }
+            }
+            b'/' if bytes.get(index + 1) == Some(&b'*') => {
+                index += 2;
+                while index + 1 < bytes.len() && !(bytes[index] == b'*' && bytes[index + 1] == b'/')
+                {
+                    index += 1;
+                }
+                index += 1;
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b';' if depth == 0 => {
+                statements.push(&code[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    statements.push(&code[start.min(code.len())..]);
+
+    statements
+        .into_iter()
+        .filter(|statement| !statement.trim().is_empty())
+        .collect()
+}
+
+fn leading_identifier(statement: &str) -> Option<(&str, &str)> {
+    let statement = statement.trim_start();
+    let end = statement
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(statement.len());
+    let name = &statement[..end];
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((name, &statement[end..]))
+}
+
+fn starts_with_keyword(statement: &str) -> bool {
+    let statement = statement.trim_start();
+    if statement.starts_with('{') {
+        return true;
+    }
+    leading_identifier(statement).is_some_and(|(name, _)| STATEMENT_KEYWORDS.contains(&name))
+}
+
+/// The name declared by a statement like `let name = ...`.
+fn declaration_target(statement: &str) -> Option<&str> {
+    let (keyword, rest) = leading_identifier(statement)?;
+    if !matches!(keyword, "let" | "const" | "var") {
+        return None;
+    }
+    let (name, _) = leading_identifier(rest)?;
+
+    Some(name)
+}
+
+/// The names assigned by a statement like `name = ...` or `a = b = ...`.
+fn assignment_targets(mut statement: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    while let Some((name, rest)) = leading_identifier(statement) {
+        if STATEMENT_KEYWORDS.contains(&name) {
+            break;
+        }
+
+        let rest = rest.trim_start();
+        if !rest.starts_with('=') || rest.starts_with("==") || rest.starts_with("=>") {
+            break;
+        }
+        names.push(name);
+        statement = &rest[1..];
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        let queries: Vec<Box<dyn Query>> = vec![
+            Box::from(Filter {
+                set_group: "assigned".into(),
+                get_group: "main".into(),
+                code: "my_frame = frame('main', i); my_frame.width == 0".into(),
+            }),
+            Box::from(Filter {
+                set_group: "not_leaked".into(),
+                get_group: "main".into(),
+                code: "typeof my_frame == 'undefined'".into(),
+            }),
+            Box::from(Filter {
+                set_group: "chained".into(),
+                get_group: "main".into(),
+                code: "a = b = 2; a + b == 4".into(),
+            }),
+            Box::from(Filter {
+                set_group: "none_leaked".into(),
+                get_group: "main".into(),
+                code: "typeof a == 'undefined' && typeof b == 'undefined' && typeof nested == 'undefined'"
+                    .into(),
+            }),
+            Box::from(Filter {
+                set_group: "shadowed".into(),
+                get_group: "main".into(),
+                code: "let i = 5; i == 5".into(),
+            }),
+            Box::from(Filter {
+                set_group: "after_shadowed".into(),
+                get_group: "main".into(),
+                code: "i == 0".into(),
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        {
+            let groups = data.groups.lock().unwrap();
+            for name in [
+                "assigned",
+                "not_leaked",
+                "chained",
+                "none_leaked",
+                "shadowed",
+                "after_shadowed",
+            ] {
+                assert_eq!(groups[name], vec![vec![0]], "{}", name);
+            }
+        }
+
+        // Assigning an undeclared name anywhere but the top level throws in strict mode
+        let mut nested = Filter {
+            set_group: "nested".into(),
+            get_group: "main".into(),
+            code: "if (true) { nested = 1 }; true".into(),
+        };
+        let err = nested.query(&mut data).unwrap_err();
+        assert!(err.starts_with("Could not evaluate 'if (true) { nested = 1 }; true'"));
+        assert!(!data.groups.lock().unwrap().contains_key("nested"));
+    }
+
+    #[test]
+    fn compiled_once() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        for _ in 0..3 {
+            let mut filter = Filter {
+                set_group: "output".into(),
+                get_group: "main".into(),
+                code: "area(group_index('main', i, 0)) == 0".into(),
+            };
+
+            if let Err(err) = filter.query(&mut data) {
+                println!("Error: {}", err);
+                assert!(false);
+            }
+        }
+
+        assert_eq!(data.expressions.len(), 1);
+    }
+
+    #[test]
+    fn syntax_error() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        let mut filter = Filter {
+            set_group: "output".into(),
+            get_group: "main".into(),
+            code: "frame('main', i".into(),
+        };
+
+        assert!(filter.query(&mut data).is_err());
+    }
+}
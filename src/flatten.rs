@@ -0,0 +1,302 @@
+use std::f64::consts::{PI, TAU};
+
+use geo::{Coord, LineString, Polygon, coord};
+
+/// How many times a curve is split before checking it against the tolerance, so an S shaped
+/// piece isn't mistaken for a straight one.
+const PIECES: usize = 8;
+const MAX_DEPTH: usize = 16;
+
+fn distance(a: Coord, b: Coord) -> f64 {
+    (a - b).x.hypot((a - b).y)
+}
+
+/// The distance from `point` to the line through `a` and `b`.
+fn deviation(point: Coord, a: Coord, b: Coord) -> f64 {
+    let length = distance(a, b);
+    if length == 0.0 {
+        return distance(point, a);
+    }
+    ((b - a).x * (a - point).y - (a - point).x * (b - a).y).abs() / length
+}
+
+fn subdivide(
+    curve: &impl Fn(f64) -> Coord,
+    (t0, p0): (f64, Coord),
+    (t1, p1): (f64, Coord),
+    tolerance: f64,
+    depth: usize,
+    points: &mut Vec<Coord>,
+) {
+    let tm = (t0 + t1) / 2.0;
+    let pm = curve(tm);
+    if depth < MAX_DEPTH && deviation(pm, p0, p1) > tolerance {
+        subdivide(curve, (t0, p0), (tm, pm), tolerance, depth + 1, points);
+        subdivide(curve, (tm, pm), (t1, p1), tolerance, depth + 1, points);
+    } else {
+        points.push(p1);
+    }
+}
+
+/// Points along `curve` from `t0` to `t1`, both ends included, that stay within `tolerance`
+/// of it.
+pub(crate) fn flatten_curve(
+    curve: impl Fn(f64) -> Coord,
+    t0: f64,
+    t1: f64,
+    tolerance: f64,
+) -> Vec<Coord> {
+    let mut points = vec![curve(t0)];
+    for piece in 0..PIECES {
+        let start = t0 + (t1 - t0) * piece as f64 / PIECES as f64;
+        let end = t0 + (t1 - t0) * (piece + 1) as f64 / PIECES as f64;
+        let from = (start, *points.last().unwrap());
+        subdivide(&curve, from, (end, curve(end)), tolerance, 0, &mut points);
+    }
+    points
+}
+
+/// Points along the arc around `center` starting at `start` radians and turning `sweep`
+/// radians, counter clockwise when positive. Both ends are included.
+pub(crate) fn arc(
+    center: Coord,
+    radius: f64,
+    start: f64,
+    sweep: f64,
+    tolerance: f64,
+) -> Vec<Coord> {
+    let step = if tolerance < radius {
+        2.0 * (1.0 - tolerance / radius).acos()
+    } else {
+        PI / 2.0
+    };
+    let segments = ((sweep.abs() / step).ceil() as usize).clamp(1, 1 << 16);
+
+    (0..=segments)
+        .map(|segment| {
+            let angle = start + sweep * segment as f64 / segments as f64;
+            coord! {x: center.x + radius * angle.cos(), y: center.y + radius * angle.sin()}
+        })
+        .collect()
+}
+
+/// Points along the arc from `from` to `to` with the given bulge, the tangent of a quarter of
+/// the angle it turns as used by DXF polylines. Both ends are included.
+pub(crate) fn bulge(from: Coord, to: Coord, bulge: f64, tolerance: f64) -> Vec<Coord> {
+    let chord = distance(from, to);
+    if bulge.abs() < 1e-12 || chord == 0.0 {
+        return vec![from, to];
+    }
+
+    let sweep = 4.0 * bulge.atan();
+    let radius = chord / (2.0 * (sweep / 2.0).sin()).abs();
+    // The center is to the left of the chord for counter clockwise arcs
+    let offset = chord / 2.0 * (1.0 - bulge * bulge) / (2.0 * bulge);
+    let normal = coord! {x: -(to - from).y / chord, y: (to - from).x / chord};
+    let center = (from + to) / 2.0 + normal * offset;
+    let start = (from - center).y.atan2((from - center).x);
+
+    let mut points = arc(center, radius, start, sweep, tolerance);
+    // Land exactly on the vertices so neighbouring segments still meet
+    points[0] = from;
+    *points.last_mut().unwrap() = to;
+    points
+}
+
+/// Points along the ellipse around `center` with the major axis ending at `center + major`
+/// and the minor axis `ratio` times as long, from parameter `start` to `end` radians.
+pub(crate) fn ellipse(
+    center: Coord,
+    major: Coord,
+    ratio: f64,
+    start: f64,
+    end: f64,
+    tolerance: f64,
+) -> Vec<Coord> {
+    let minor = coord! {x: -major.y * ratio, y: major.x * ratio};
+    let end = if end <= start { end + TAU } else { end };
+    flatten_curve(
+        |t| center + major * t.cos() + minor * t.sin(),
+        start,
+        end,
+        tolerance,
+    )
+}
+
+/// Points along a B-spline, rational when `weights` has a weight for every control point.
+/// Missing or inconsistent knots are replaced by a clamped uniform knot vector.
+pub(crate) fn spline(
+    degree: usize,
+    control_points: &[Coord],
+    knots: &[f64],
+    weights: &[f64],
+    tolerance: f64,
+) -> Vec<Coord> {
+    let count = control_points.len();
+    if count < 2 {
+        return control_points.to_vec();
+    }
+    let degree = degree.clamp(1, count - 1);
+
+    let knots: Vec<f64> = if knots.len() == count + degree + 1 {
+        knots.to_vec()
+    } else {
+        let spans = (count - degree) as f64;
+        (0..count + degree + 1)
+            .map(|index| (index.saturating_sub(degree) as f64).min(spans) / spans)
+            .collect()
+    };
+    let points: Vec<[f64; 3]> = control_points
+        .iter()
+        .enumerate()
+        .map(|(index, point)| {
+            let weight = if weights.len() == count {
+                weights[index]
+            } else {
+                1.0
+            };
+            [point.x * weight, point.y * weight, weight]
+        })
+        .collect();
+
+    // de Boor's algorithm in homogeneous coordinates
+    let evaluate = |t: f64| {
+        let mut span = degree;
+        while span + 1 < count && knots[span + 1] <= t {
+            span += 1;
+        }
+        let mut d: Vec<[f64; 3]> = (0..=degree).map(|j| points[j + span - degree]).collect();
+        for r in 1..=degree {
+            for j in (r..=degree).rev() {
+                let i = j + span - degree;
+                let denominator = knots[i + degree + 1 - r] - knots[i];
+                let alpha = if denominator == 0.0 {
+                    0.0
+                } else {
+                    (t - knots[i]) / denominator
+                };
+                let previous = d[j - 1];
+                for (value, previous) in d[j].iter_mut().zip(previous) {
+                    *value = (1.0 - alpha) * previous + alpha * *value;
+                }
+            }
+        }
+        let [x, y, w] = d[degree];
+        coord! {x: x / w, y: y / w}
+    };
+
+    let mut result: Vec<Coord> = Vec::new();
+    for span in degree..count {
+        let (t0, t1) = (knots[span], knots[span + 1]);
+        if t1 <= t0 {
+            continue;
+        }
+        let points = flatten_curve(&evaluate, t0, t1, tolerance);
+        let skip = if result.is_empty() { 0 } else { 1 };
+        result.extend(points.into_iter().skip(skip));
+    }
+    result
+}
+
+/// A polygon from a ring of points, or `None` when it doesn't enclose anything.
+pub(crate) fn ring_to_polygon(mut points: Vec<Coord>, tolerance: f64) -> Option<Polygon> {
+    points.dedup_by(|a, b| distance(*a, *b) <= tolerance / 2.0);
+    if points.len() > 1 && distance(points[0], *points.last().unwrap()) <= tolerance {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return None;
+    }
+    Some(Polygon::new(LineString::new(points), Vec::new()))
+}
+
+/// Joins open paths whose ends meet within `tolerance` into closed rings. Paths that can't be
+/// closed are dropped, since they don't outline a shape.
+pub(crate) fn chain(mut paths: Vec<Vec<Coord>>, tolerance: f64) -> Vec<Vec<Coord>> {
+    paths.retain(|path| path.len() > 1);
+    let mut rings = Vec::new();
+
+    while let Some(mut ring) = paths.pop() {
+        loop {
+            let (first, last) = (ring[0], *ring.last().unwrap());
+            if ring.len() > 2 && distance(first, last) <= tolerance {
+                rings.push(ring);
+                break;
+            }
+
+            let next = paths.iter().position(|path| {
+                distance(path[0], last) <= tolerance
+                    || distance(*path.last().unwrap(), last) <= tolerance
+            });
+            let Some(next) = next else {
+                break;
+            };
+            let mut path = paths.swap_remove(next);
+            if distance(path[0], last) > tolerance {
+                path.reverse();
+            }
+            ring.extend(path.into_iter().skip(1));
+        }
+    }
+
+    rings
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, coord};
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let tolerance = 0.001;
+
+        // Two half circles of radius 1 chained into a circle
+        let top = bulge(
+            coord! {x: 1.0, y: 0.0},
+            coord! {x: -1.0, y: 0.0},
+            1.0,
+            tolerance,
+        );
+        let bottom = bulge(
+            coord! {x: 1.0, y: 0.0},
+            coord! {x: -1.0, y: 0.0},
+            -1.0,
+            tolerance,
+        );
+        assert!(top.iter().all(|point| point.y >= -1e-9));
+        assert!(bottom.iter().all(|point| point.y <= 1e-9));
+
+        let rings = chain(vec![top, bottom], tolerance);
+        assert_eq!(rings.len(), 1);
+        let circle = ring_to_polygon(rings[0].clone(), tolerance).unwrap();
+        assert!((circle.unsigned_area() - PI).abs() < 0.01);
+
+        // A quadratic B-spline with a clamped knot vector starts and ends on its control points
+        let points = spline(
+            2,
+            &[
+                coord! {x: 0.0, y: 0.0},
+                coord! {x: 1.0, y: 2.0},
+                coord! {x: 2.0, y: 0.0},
+            ],
+            &[],
+            &[],
+            tolerance,
+        );
+        assert_eq!(points[0], coord! {x: 0.0, y: 0.0});
+        assert!(distance(*points.last().unwrap(), coord! {x: 2.0, y: 0.0}) < 1e-9);
+        let top = points.iter().map(|point| point.y).fold(0.0, f64::max);
+        assert!((top - 1.0).abs() < tolerance);
+
+        // An open path is dropped
+        assert!(
+            chain(
+                vec![vec![coord! {x: 0.0, y: 0.0}, coord! {x: 1.0, y: 0.0}]],
+                tolerance
+            )
+            .is_empty()
+        );
+    }
+}
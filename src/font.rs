@@ -0,0 +1,354 @@
+use geo::{Coord, Polygon, coord};
+use ttf_parser::{
+    Face, GlyphId, OutlineBuilder, Tag,
+    gpos::{PairAdjustment, PositioningSubtable},
+};
+
+use crate::flatten::{flatten_curve, ring_to_polygon};
+
+/// Collects the contours of a glyph, flattened and placed at `origin` with `scale` output units
+/// per font unit.
+struct Outline {
+    origin: Coord,
+    scale: f64,
+    tolerance: f64,
+    contours: Vec<Vec<Coord>>,
+    current: Vec<Coord>,
+}
+
+impl Outline {
+    fn point(&self, x: f32, y: f32) -> Coord {
+        self.origin + coord! {x: x as f64, y: y as f64} * self.scale
+    }
+
+    fn last(&self) -> Coord {
+        self.current.last().copied().unwrap_or(self.origin)
+    }
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.current.push(self.point(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.current.push(self.point(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last(), self.point(x1, y1), self.point(x, y));
+        let points = flatten_curve(
+            |t| p0 * ((1.0 - t) * (1.0 - t)) + p1 * (2.0 * (1.0 - t) * t) + p2 * (t * t),
+            0.0,
+            1.0,
+            self.tolerance,
+        );
+        self.current.extend(points.into_iter().skip(1));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (
+            self.last(),
+            self.point(x1, y1),
+            self.point(x2, y2),
+            self.point(x, y),
+        );
+        let points = flatten_curve(
+            |t| {
+                let u = 1.0 - t;
+                p0 * (u * u * u)
+                    + p1 * (3.0 * u * u * t)
+                    + p2 * (3.0 * u * t * t)
+                    + p3 * (t * t * t)
+            },
+            0.0,
+            1.0,
+            self.tolerance,
+        );
+        self.current.extend(points.into_iter().skip(1));
+    }
+
+    fn close(&mut self) {
+        if !self.current.is_empty() {
+            self.contours.push(std::mem::take(&mut self.current));
+        }
+    }
+}
+
+/// The advance adjustment between two glyphs from the pair positioning of the `kern` feature
+/// in `GPOS`, in font units.
+fn gpos_kerning(face: &Face, left: GlyphId, right: GlyphId) -> Option<i16> {
+    let gpos = face.tables().gpos?;
+    let kern = Tag::from_bytes(b"kern");
+
+    for feature in gpos
+        .features
+        .into_iter()
+        .filter(|feature| feature.tag == kern)
+    {
+        for index in feature.lookup_indices {
+            let Some(lookup) = gpos.lookups.get(index) else {
+                continue;
+            };
+            for subtable in lookup.subtables.into_iter::<PositioningSubtable>() {
+                let PositioningSubtable::Pair(pair) = subtable else {
+                    continue;
+                };
+                let records = match pair {
+                    PairAdjustment::Format1 { coverage, sets } => coverage
+                        .get(left)
+                        .and_then(|index| sets.get(index))
+                        .and_then(|set| set.get(right)),
+                    PairAdjustment::Format2 {
+                        coverage,
+                        classes,
+                        matrix,
+                    } => coverage
+                        .get(left)
+                        .and_then(|_| matrix.get((classes.0.get(left), classes.1.get(right)))),
+                };
+                if let Some((record, _)) = records {
+                    return Some(record.x_advance);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// The kerning between two glyphs in font units, from `GPOS` when the font has it and from the
+/// older `kern` table otherwise.
+fn font_kerning(face: &Face, left: GlyphId, right: GlyphId) -> f64 {
+    if face.tables().gpos.is_some() {
+        return gpos_kerning(face, left, right).map_or(0.0, f64::from);
+    }
+
+    face.tables()
+        .kern
+        .and_then(|kern| {
+            kern.subtables
+                .into_iter()
+                .filter(|subtable| {
+                    subtable.horizontal
+                        && !subtable.variable
+                        && !subtable.has_cross_stream
+                        && !subtable.has_state_machine
+                })
+                .find_map(|subtable| subtable.glyphs_kerning(left, right))
+        })
+        .map_or(0.0, f64::from)
+}
+
+/// Lays out `text` with the TrueType or OpenType font in `font`.
+///
+/// The font is scaled so that its em is `size` units, the first baseline starts at `origin`
+/// and every `\n` starts a new line below. Each glyph with an outline gives one entry with a
+/// polygon per contour, counters included, flattened to within `tolerance`. With `kerning` the
+/// pair adjustments of the font are applied, otherwise glyphs are only spaced by their advance.
+pub fn text_to_polygons(
+    font: &[u8],
+    text: &str,
+    size: f64,
+    origin: Coord,
+    kerning: bool,
+    tolerance: f64,
+) -> Result<Vec<Vec<Polygon>>, String> {
+    let face = Face::parse(font, 0).map_err(|err| format!("Could not read font: {}", err))?;
+    let scale = size / f64::from(face.units_per_em());
+    let line_height =
+        f64::from(face.ascender()) - f64::from(face.descender()) + f64::from(face.line_gap());
+
+    let mut glyphs = Vec::new();
+    let mut pen = coord! {x: 0.0, y: 0.0};
+    let mut previous: Option<GlyphId> = None;
+
+    for c in text.chars() {
+        if c == '\n' {
+            pen = coord! {x: 0.0, y: pen.y - line_height};
+            previous = None;
+            continue;
+        }
+        let Some(glyph) = face.glyph_index(c) else {
+            return Err(format!("Could not find a glyph for '{}' in the font.", c));
+        };
+
+        if let (true, Some(previous)) = (kerning, previous) {
+            pen.x += font_kerning(&face, previous, glyph);
+        }
+
+        let mut outline = Outline {
+            origin: origin + pen * scale,
+            scale,
+            tolerance,
+            contours: Vec::new(),
+            current: Vec::new(),
+        };
+        face.outline_glyph(glyph, &mut outline);
+        outline.close();
+
+        let polygons: Vec<Polygon> = outline
+            .contours
+            .into_iter()
+            .filter_map(|contour| ring_to_polygon(contour, tolerance))
+            .collect();
+        if !polygons.is_empty() {
+            glyphs.push(polygons);
+        }
+
+        pen.x += f64::from(face.glyph_hor_advance(glyph).unwrap_or(0));
+        previous = Some(glyph);
+    }
+
+    Ok(glyphs)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use geo::{Area, BoundingRect, MultiPolygon, coord};
+
+    use super::*;
+
+    fn glyph(contours: &[&[(i16, i16)]]) -> Vec<u8> {
+        let points: Vec<(i16, i16)> = contours
+            .iter()
+            .flat_map(|contour| contour.iter())
+            .copied()
+            .collect();
+        let mut data = Vec::new();
+        data.extend((contours.len() as i16).to_be_bytes());
+        data.extend([0; 8]);
+        let mut end = 0;
+        for contour in contours {
+            end += contour.len() as u16;
+            data.extend((end - 1).to_be_bytes());
+        }
+        data.extend(0u16.to_be_bytes());
+        // Every point on the curve with two byte deltas
+        data.extend(std::iter::repeat_n(1u8, points.len()));
+        let mut previous = (0, 0);
+        for (x, _) in &points {
+            data.extend((x - previous.0).to_be_bytes());
+            previous.0 = *x;
+        }
+        for (_, y) in &points {
+            data.extend((y - previous.1).to_be_bytes());
+            previous.1 = *y;
+        }
+        data
+    }
+
+    /// A font with an `I` and an `O` with a counter, 1000 units per em and a `kern` table
+    /// moving `I` 100 units closer after `O`.
+    pub(crate) fn font() -> Vec<u8> {
+        let o = glyph(&[
+            &[(0, 0), (0, 600), (600, 600), (600, 0)],
+            &[(150, 150), (450, 150), (450, 450), (150, 450)],
+        ]);
+        let i = glyph(&[&[(0, 0), (0, 700), (200, 700), (200, 0)]]);
+
+        let mut cmap = vec![0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 12];
+        cmap.extend([0, 6, 0, 24, 0, 0, 0, b'I', 0, 7]);
+        cmap.extend([0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let mut head = vec![
+            0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x5F, 0x0F, 0x3C, 0xF5, 0, 0,
+        ];
+        head.extend(1000u16.to_be_bytes());
+        head.extend([0; 34]);
+
+        let mut hhea = vec![0, 1, 0, 0];
+        hhea.extend(800i16.to_be_bytes());
+        hhea.extend((-200i16).to_be_bytes());
+        hhea.extend([0; 26]);
+        hhea.extend(3u16.to_be_bytes());
+
+        let mut hmtx = Vec::new();
+        for advance in [500u16, 700, 300] {
+            hmtx.extend(advance.to_be_bytes());
+            hmtx.extend([0, 0]);
+        }
+
+        let mut kern = vec![
+            0, 0, 0, 1, 0, 0, 0, 20, 0, 1, 0, 1, 0, 6, 0, 0, 0, 0, 0, 1, 0, 2,
+        ];
+        kern.extend((-100i16).to_be_bytes());
+
+        let mut loca = Vec::new();
+        for offset in [0, 0, o.len(), o.len() + i.len()] {
+            loca.extend(((offset / 2) as u16).to_be_bytes());
+        }
+
+        let glyf = [o, i].concat();
+        let maxp = vec![0, 0, 0x50, 0, 0, 3];
+
+        let tables: [(&[u8; 4], Vec<u8>); 8] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"kern", kern),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+
+        let mut font = vec![0, 1, 0, 0, 0, tables.len() as u8, 0, 0, 0, 0, 0, 0];
+        let mut offset = 12 + 16 * tables.len();
+        let mut body = Vec::new();
+        for (tag, table) in &tables {
+            font.extend(*tag);
+            font.extend([0; 4]);
+            font.extend((offset as u32).to_be_bytes());
+            font.extend((table.len() as u32).to_be_bytes());
+
+            body.extend(table);
+            body.resize(body.len().next_multiple_of(4), 0);
+            offset = 12 + 16 * tables.len() + body.len();
+        }
+        font.extend(body);
+        font
+    }
+
+    fn frame(polygons: &[Polygon]) -> geo::Rect {
+        MultiPolygon::new(polygons.to_vec())
+            .bounding_rect()
+            .unwrap()
+    }
+
+    #[test]
+    fn it_works() {
+        let font = font();
+        let origin = coord! {x: 10.0, y: 20.0};
+
+        let glyphs = match text_to_polygons(&font, "OI", 2.0, origin, false, 0.001) {
+            Ok(glyphs) => glyphs,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        assert_eq!(glyphs.len(), 2);
+        assert_eq!(glyphs[0].len(), 2);
+        assert_eq!(glyphs[1].len(), 1);
+        assert!((glyphs[0][0].unsigned_area() - 1.44).abs() < 1e-9);
+        assert!((glyphs[0][1].unsigned_area() - 0.36).abs() < 1e-9);
+        assert!((frame(&glyphs[0]).min().x - 10.0).abs() < 1e-9);
+        assert!((frame(&glyphs[1]).min().x - 11.4).abs() < 1e-9);
+        assert!((frame(&glyphs[1]).max().y - 21.4).abs() < 1e-9);
+
+        // The kern table pulls the I 100 units closer
+        let kerned = text_to_polygons(&font, "OI", 2.0, origin, true, 0.001).unwrap();
+        assert!((frame(&kerned[1]).min().x - 11.2).abs() < 1e-9);
+
+        // A new line starts an em and the line gap below
+        let lines = text_to_polygons(&font, "I\nI", 2.0, origin, false, 0.001).unwrap();
+        assert!((frame(&lines[1]).min().x - 10.0).abs() < 1e-9);
+        assert!((frame(&lines[1]).min().y - 18.0).abs() < 1e-9);
+
+        assert!(text_to_polygons(&font, "X", 2.0, origin, false, 0.001).is_err());
+        assert!(text_to_polygons(&[0; 12], "I", 2.0, origin, false, 0.001).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+use std::{f64::consts::TAU, iter::Peekable, str::Chars};
+
+use geo::{Coord, Polygon, coord};
+
+use crate::{
+    Data,
+    flatten::{arc, chain, ring_to_polygon},
+};
+
+const MM_PER_INCH: f64 = 25.4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Rapid,
+    Linear,
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Skips a `#` variable or a `[...]` expression, whose value is only known while the
+/// program runs.
+fn skip_expression(chars: &mut Peekable<Chars>) {
+    let mut depth = 0;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            '#' => {}
+            c if depth > 0 || c.is_ascii_digit() || c == '.' => {}
+            _ => break,
+        }
+        chars.next();
+        if c == ']' && depth == 0 {
+            break;
+        }
+    }
+}
+
+/// The letters and numbers of one line with comments removed, like `G1`, `X-1.5`.
+///
+/// Block delete lines starting with `/`, `%` lines and checksums after `*` are left out, and
+/// so are variable assignments and the words given by a `#` variable or a `[...]` expression.
+fn words(line: &str, number: usize) -> Result<Vec<(char, f64)>, String> {
+    let mut words = Vec::new();
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('/') || trimmed.starts_with('%') {
+        return Ok(words);
+    }
+    let mut chars = line.chars().peekable();
+    let mut in_comment = false;
+
+    while let Some(c) = chars.next() {
+        if in_comment {
+            in_comment = c != ')';
+            continue;
+        }
+        match c {
+            '(' => in_comment = true,
+            ';' | '*' | '#' => break,
+            '[' => skip_expression(&mut chars),
+            '%' => {}
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_alphabetic() => {
+                let mut value = String::new();
+                while let Some(next) = chars.peek() {
+                    if next.is_ascii_digit() || *next == '.' || *next == '-' || *next == '+' {
+                        value.push(*next);
+                        chars.next();
+                    } else if next.is_whitespace() && value.is_empty() {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if value.is_empty() && matches!(chars.peek(), Some('#' | '[')) {
+                    skip_expression(&mut chars);
+                    continue;
+                }
+                let value = value.parse::<f64>().map_err(|_| {
+                    format!(
+                        "Invalid number '{}' after '{}' on line {}.",
+                        value, c, number
+                    )
+                })?;
+                words.push((c.to_ascii_uppercase(), value));
+            }
+            c => return Err(format!("Unexpected '{}' on line {}.", c, number)),
+        }
+    }
+
+    Ok(words)
+}
+
+/// The center of the arc from `from` to `to` with radius `radius`. A negative radius picks the
+/// arc that turns more than half a circle, as in the `R` form of `G2` and `G3`.
+fn center_from_radius(from: Coord, to: Coord, radius: f64, clockwise: bool) -> Option<Coord> {
+    let chord = to - from;
+    let length = chord.x.hypot(chord.y);
+    if length == 0.0 {
+        return None;
+    }
+
+    let height = (radius * radius - length * length / 4.0).max(0.0).sqrt();
+    // The short arc turns around a center to the left of the chord when counter clockwise
+    let side = if clockwise == (radius < 0.0) {
+        1.0
+    } else {
+        -1.0
+    };
+    let normal = coord! {x: -chord.y / length, y: chord.x / length};
+    Some((from + to) / 2.0 + normal * (height * side))
+}
+
+/// Points along the arc from `from` to `to` around `center`, both ends included. Ends that
+/// meet make a full circle.
+fn arc_points(
+    from: Coord,
+    to: Coord,
+    center: Coord,
+    clockwise: bool,
+    tolerance: f64,
+) -> Vec<Coord> {
+    let start = (from - center).y.atan2((from - center).x);
+    let end = (to - center).y.atan2((to - center).x);
+    let radius = (from - center).x.hypot((from - center).y);
+
+    let mut sweep = if clockwise { start - end } else { end - start };
+    sweep = sweep.rem_euclid(TAU);
+    if sweep < 1e-9 {
+        sweep = TAU;
+    }
+    if clockwise {
+        sweep = -sweep;
+    }
+
+    let mut points = arc(center, radius, start, sweep, tolerance);
+    *points.last_mut().unwrap() = to;
+    points
+}
+
+/// Whether two polygons trace the same outline, like the passes of a multi pass cut.
+fn same_outline(l: &Polygon, r: &Polygon, tolerance: f64) -> bool {
+    let (l, r) = (&l.exterior().0, &r.exterior().0);
+    l.len() == r.len()
+        && l.iter()
+            .zip(r)
+            .all(|(l, r)| (l.x - r.x).hypot(l.y - r.y) <= tolerance)
+}
+
+/// Rebuilds the outlines cut by a G-code program.
+///
+/// Feed moves (`G1`, `G2` and `G3`, with `I`/`J` or `R` arcs) at or below `Z0` cut, while
+/// rapid moves and moves above the stock end the current path. Paths are chained into closed
+/// outlines, outlines cut more than once are kept once and open paths are left out. `G20`,
+/// `G21`, `G90`, `G91`, `G90.1` and `G91.1` are followed, and every coordinate is converted
+/// to inches, the unit `polygons_to_svg` writes. `tolerance` is in inches too.
+pub fn gcode_to_polygons(gcode: &str, tolerance: f64) -> Result<Vec<Polygon>, String> {
+    let mut scale = 1.0 / MM_PER_INCH;
+    let mut absolute = true;
+    let mut absolute_centers = false;
+    let mut motion = Motion::Rapid;
+    let mut position = coord! {x: 0.0, y: 0.0};
+    let mut z = 0.0;
+
+    let mut paths: Vec<Vec<Coord>> = Vec::new();
+    let mut path: Vec<Coord> = Vec::new();
+
+    for (index, line) in gcode.lines().enumerate() {
+        let words = words(line, index + 1)?;
+
+        let mut skip = false;
+        for (letter, value) in &words {
+            if *letter != 'G' {
+                continue;
+            }
+            match (value * 10.0).round() as i64 {
+                0 => motion = Motion::Rapid,
+                10 => motion = Motion::Linear,
+                20 => motion = Motion::Clockwise,
+                30 => motion = Motion::CounterClockwise,
+                200 => scale = 1.0,
+                210 => scale = 1.0 / MM_PER_INCH,
+                900 => absolute = true,
+                910 => absolute = false,
+                901 => absolute_centers = true,
+                911 => absolute_centers = false,
+                // Dwell and moves in machine coordinates don't trace the part
+                40 | 280 | 300 | 530 | 920 => skip = true,
+                _ => {}
+            }
+        }
+        if skip {
+            continue;
+        }
+
+        let word = |letter: char| {
+            words
+                .iter()
+                .find(|(word, _)| *word == letter)
+                .map(|(_, value)| value * scale)
+        };
+        let (x, y) = (word('X'), word('Y'));
+        if let Some(new_z) = word('Z') {
+            z = if absolute { new_z } else { z + new_z };
+        }
+
+        let target = if absolute {
+            coord! {x: x.unwrap_or(position.x), y: y.unwrap_or(position.y)}
+        } else {
+            position + coord! {x: x.unwrap_or(0.0), y: y.unwrap_or(0.0)}
+        };
+
+        let cutting = motion != Motion::Rapid && z <= tolerance;
+        if !cutting {
+            if path.len() > 1 {
+                paths.push(std::mem::take(&mut path));
+            }
+            path.clear();
+            position = target;
+            continue;
+        }
+        if x.is_none() && y.is_none() && word('I').is_none() && word('J').is_none() {
+            continue;
+        }
+
+        if path.is_empty() {
+            path.push(position);
+        }
+        match motion {
+            Motion::Clockwise | Motion::CounterClockwise => {
+                let clockwise = motion == Motion::Clockwise;
+                let center = match word('R') {
+                    Some(radius) => center_from_radius(position, target, radius, clockwise),
+                    None => {
+                        let offset =
+                            coord! {x: word('I').unwrap_or(0.0), y: word('J').unwrap_or(0.0)};
+                        Some(if absolute_centers {
+                            offset
+                        } else {
+                            position + offset
+                        })
+                    }
+                };
+                match center {
+                    Some(center) => path.extend(
+                        arc_points(position, target, center, clockwise, tolerance)
+                            .into_iter()
+                            .skip(1),
+                    ),
+                    None => path.push(target),
+                }
+            }
+            _ => path.push(target),
+        }
+        position = target;
+
+        // A contour cut again in another pass starts a new path where it closed
+        if path.len() > 3 && (target - path[0]).x.hypot((target - path[0]).y) <= tolerance {
+            paths.push(std::mem::take(&mut path));
+        }
+    }
+    if path.len() > 1 {
+        paths.push(path);
+    }
+
+    let mut polygons: Vec<Polygon> = Vec::new();
+    for ring in chain(paths, tolerance) {
+        let Some(polygon) = ring_to_polygon(ring, tolerance) else {
+            continue;
+        };
+        if !polygons
+            .iter()
+            .any(|other| same_outline(other, &polygon, tolerance))
+        {
+            polygons.push(polygon);
+        }
+    }
+
+    Ok(polygons)
+}
+
+impl Data {
+    /// Loads the outlines cut by a G-code file, see `gcode_to_polygons`.
+    pub fn from_gcode(path: &std::path::Path, tolerance: f64) -> Result<Self, String> {
+        let gcode = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+        Ok(gcode_to_polygons(&gcode, tolerance)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use geo::Area;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let gcode = "%
+(A 1 inch square cut in two passes)
+G21 G90
+G0 Z5
+G0 X0 Y0
+G1 Z-1 F100
+G1 X25.4
+Y25.4
+X0
+Y0
+G1 Z-2
+G1 X25.4 Y0 X25.4 ; the same square again
+G1 Y25.4
+G1 X0
+G1 Y0
+G0 Z5
+(A half inch triangle in relative inches)
+G20 G0 X2 Y0
+G1 Z-0.1
+G91 G1 X0.5
+Y0.5
+X-0.5 Y-0.5
+G90 G0 Z1
+(A circle of radius 0.5 from I and J)
+G0 X5 Y0
+G1 Z-0.1
+G2 X5 Y0 I0 J0.5
+G0 Z1
+(A circle made of two half circles given by R)
+G0 X8 Y0
+G1 Z-0.1
+G3 X9 Y0 R0.5
+G3 X8 Y0 R0.5
+G0 Z1
+(Never closed)
+G0 X20 Y0
+G1 Z-0.1
+G1 X21
+M30
+%";
+
+        let polygons = match gcode_to_polygons(gcode, 0.0001) {
+            Ok(polygons) => polygons,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        assert_eq!(polygons.len(), 4);
+
+        let mut areas: Vec<f64> = polygons
+            .iter()
+            .map(|polygon| polygon.unsigned_area())
+            .collect();
+        areas.sort_by(|l, r| l.partial_cmp(r).unwrap());
+        assert!((areas[0] - 0.125).abs() < 1e-9);
+        assert!((areas[1] - PI * 0.25).abs() < 0.001);
+        assert!((areas[2] - PI * 0.25).abs() < 0.001);
+        assert!((areas[3] - 1.0).abs() < 1e-9);
+
+        assert!(gcode_to_polygons("G1 X1 Y1 @", 0.0001).is_err());
+    }
+
+    #[test]
+    fn controller_syntax() {
+        let gcode = "%
+O1000 (A 1 inch square)
+#100 = 25.4
+G21 G90*12
+M3 S[#100 * 10]
+G0 Z5
+/G0 X100 Y100 (only without block delete)
+G0 X0 Y0
+G1 Z-1 F#100
+G1 X25.4*34
+Y25.4
+X0
+Y0
+G0 Z5
+M30
+%";
+
+        let polygons = match gcode_to_polygons(gcode, 0.0001) {
+            Ok(polygons) => polygons,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        assert_eq!(polygons.len(), 1);
+        assert!((polygons[0].unsigned_area() - 1.0).abs() < 1e-9);
+    }
+}
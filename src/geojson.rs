@@ -0,0 +1,253 @@
+use std::{collections::HashMap, sync::Arc};
+
+use geo::{Coord, LineString, Polygon, coord};
+use serde_json::{Value, json};
+
+use crate::Data;
+
+fn ring_to_geojson(ring: &LineString) -> Value {
+    ring.0
+        .iter()
+        .map(|point| json!([point.x, point.y]))
+        .collect()
+}
+
+fn polygon_to_geojson(polygon: &Polygon) -> Value {
+    json!({
+        "type": "Polygon",
+        "coordinates": std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .map(ring_to_geojson)
+            .collect::<Vec<Value>>(),
+    })
+}
+
+fn ring_from_geojson(ring: &Value) -> Result<LineString, String> {
+    let Some(points) = ring.as_array() else {
+        return Err(format!("Expected a ring of positions, found {}.", ring));
+    };
+    points
+        .iter()
+        .map(|point| match point.as_array().map(Vec::as_slice) {
+            Some([x, y, ..]) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) => Ok(coord! {x: x, y: y}),
+                _ => Err(format!("Expected a position, found {}.", point)),
+            },
+            _ => Err(format!("Expected a position, found {}.", point)),
+        })
+        .collect::<Result<Vec<Coord>, String>>()
+        .map(LineString::new)
+}
+
+fn polygon_from_geojson(rings: &Value) -> Result<Polygon, String> {
+    let Some(rings) = rings.as_array() else {
+        return Err(format!("Expected a list of rings, found {}.", rings));
+    };
+    let mut rings = rings
+        .iter()
+        .map(ring_from_geojson)
+        .collect::<Result<Vec<LineString>, String>>()?;
+    if rings.is_empty() {
+        return Err("Expected a polygon with at least one ring.".to_string());
+    }
+    let exterior = rings.remove(0);
+    Ok(Polygon::new(exterior, rings))
+}
+
+/// The polygons of a `Polygon` or `MultiPolygon` geometry.
+fn geometry_from_geojson(geometry: &Value) -> Result<Vec<Polygon>, String> {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str() {
+        Some("Polygon") => Ok(vec![polygon_from_geojson(coordinates)?]),
+        Some("MultiPolygon") => coordinates
+            .as_array()
+            .ok_or_else(|| format!("Expected a list of polygons, found {}.", coordinates))?
+            .iter()
+            .map(polygon_from_geojson)
+            .collect(),
+        Some(other) => Err(format!("Unsupported geometry '{}'.", other)),
+        None => Err(format!("Expected a geometry, found {}.", geometry)),
+    }
+}
+
+impl Data {
+    /// Writes every shape of `groups` as a GeoJSON FeatureCollection.
+    ///
+    /// Each shape is a `Polygon` feature with its `group`, its `sub_group` index, its `depth`
+    /// and its `shape` index as properties, so a shape in more than one group is written once
+    /// per group.
+    pub fn to_geojson(&self, groups: &[&str]) -> String {
+        let shapes = self.shapes.lock().unwrap();
+        let depths = self.depths.lock().unwrap();
+        let data_groups = self.groups.lock().unwrap();
+
+        let mut features = Vec::new();
+        for name in groups {
+            let Some(group) = data_groups.get(*name) else {
+                continue;
+            };
+            for (sub_group, indexes) in group.iter().enumerate() {
+                for index in indexes {
+                    features.push(json!({
+                        "type": "Feature",
+                        "geometry": polygon_to_geojson(&shapes[*index]),
+                        "properties": {
+                            "group": name,
+                            "sub_group": sub_group,
+                            "depth": depths[*index],
+                            "shape": index,
+                        },
+                    }));
+                }
+            }
+        }
+
+        json!({"type": "FeatureCollection", "features": features}).to_string()
+    }
+
+    /// Reads a GeoJSON FeatureCollection, Feature or geometry made of polygons.
+    ///
+    /// Every polygon becomes a shape of `main` and depths are computed from the geometry.
+    /// Features with `group` and `sub_group` properties, like the ones `Data::to_geojson`
+    /// writes, are put back into those groups, and features sharing a `shape` index share
+    /// their shape.
+    pub fn from_geojson(geojson: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(geojson)
+            .map_err(|err| format!("Could not parse GeoJSON: {}", err))?;
+
+        let features: Vec<&Value> = match value["type"].as_str() {
+            Some("FeatureCollection") => value["features"]
+                .as_array()
+                .map(|features| features.iter().collect())
+                .unwrap_or_default(),
+            Some("Feature") => vec![&value],
+            _ => Vec::new(),
+        };
+
+        let mut polygons = Vec::new();
+        let mut shared: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut placed: Vec<(String, usize, Vec<usize>)> = Vec::new();
+
+        if features.is_empty() && value["type"].as_str() != Some("FeatureCollection") {
+            polygons = geometry_from_geojson(&value)?;
+        }
+        for feature in features {
+            let properties = &feature["properties"];
+            let shape = properties["shape"].as_u64();
+
+            let indexes = match shape.and_then(|shape| shared.get(&shape)) {
+                Some(indexes) => indexes.clone(),
+                None => {
+                    let start = polygons.len();
+                    polygons.extend(geometry_from_geojson(&feature["geometry"])?);
+                    let indexes: Vec<usize> = (start..polygons.len()).collect();
+                    if let Some(shape) = shape {
+                        shared.insert(shape, indexes.clone());
+                    }
+                    indexes
+                }
+            };
+
+            if let (Some(group), Some(sub_group)) = (
+                properties["group"].as_str(),
+                properties["sub_group"].as_u64(),
+            ) {
+                placed.push((group.to_string(), sub_group as usize, indexes));
+            }
+        }
+
+        let (data, order) = Data::from_respect_indexes(polygons);
+        let mut moved = vec![0; order.len()];
+        for (index, original) in order.into_iter().enumerate() {
+            moved[original] = index;
+        }
+
+        // Groups from the features replace the `main` made above when they have one
+        let mut groups: HashMap<String, Vec<Vec<usize>>> = HashMap::new();
+        for (name, sub_group, indexes) in placed {
+            let group = groups.entry(name).or_default();
+            if group.len() <= sub_group {
+                group.resize(sub_group + 1, Vec::new());
+            }
+            group[sub_group].extend(indexes.into_iter().map(|index| moved[index]));
+        }
+        Arc::make_mut(&mut data.groups.lock().unwrap()).extend(groups);
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, polygon};
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let data = match Data::from_geojson(
+            r#"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]]
+                        },
+                        "properties": {"group": "letters", "sub_group": 1}
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "MultiPolygon",
+                            "coordinates": [
+                                [[[-5, -5], [5, -5], [5, 5], [-5, 5], [-5, -5]]],
+                                [[[2, 2], [3, 2], [3, 3], [2, 3], [2, 2]]]
+                            ]
+                        },
+                        "properties": {"group": "letters", "sub_group": 0}
+                    }
+                ]
+            }"#,
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+
+        let shapes = data.shapes.lock().unwrap().clone();
+        let depths = data.depths.lock().unwrap().clone();
+        let letters = data.groups.lock().unwrap()["letters"].clone();
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(data.groups.lock().unwrap()["main"].len(), 3);
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].len(), 2);
+        assert!((shapes[letters[1][0]].unsigned_area() - 1.0).abs() < 1e-9);
+        assert_eq!(depths[letters[1][0]], 1);
+        assert_eq!(depths[letters[0][1]], 1);
+
+        // Written and read back, `main` keeps one shape per feature
+        let again = Data::from_geojson(&data.to_geojson(&["main", "letters"])).unwrap();
+        assert_eq!(again.shapes.lock().unwrap().len(), 3);
+        assert_eq!(again.groups.lock().unwrap()["letters"].len(), 2);
+        assert_eq!(again.groups.lock().unwrap()["main"].len(), 3);
+
+        let square = Data::from_geojson(
+            r#"{"type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 0]]]}"#,
+        )
+        .unwrap();
+        assert_eq!(square.shapes.lock().unwrap().len(), 1);
+
+        assert!(Data::from_geojson(r#"{"type": "Point", "coordinates": [0, 0]}"#).is_err());
+        assert!(Data::from_geojson("{").is_err());
+
+        let data = Data::from(vec![
+            polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 0.0, y: 1.0)],
+        ]);
+        assert!(data.to_geojson(&["main"]).contains("\"depth\":0"));
+    }
+}
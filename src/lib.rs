@@ -1,11 +1,68 @@
 pub mod data;
 pub use data::*;
 
+pub mod shapes;
+pub use shapes::*;
+
+pub mod expression;
+pub use expression::*;
+
+pub mod observer;
+pub use observer::*;
+
 pub mod query;
 pub use query::*;
 
 pub mod queries;
 pub use queries::*;
 
+pub mod snapshot;
+pub use snapshot::*;
+
+pub mod session;
+pub use session::*;
+
+pub mod trace;
+pub use trace::*;
+
 pub mod save_svg;
 pub use save_svg::*;
+
+pub mod save_pdf;
+pub use save_pdf::*;
+
+pub mod save_png;
+pub use save_png::*;
+
+pub mod save_hpgl;
+pub use save_hpgl::*;
+
+pub mod save_stl;
+pub use save_stl::*;
+
+pub mod debug_svg;
+pub use debug_svg::*;
+
+mod flatten;
+mod triangulate;
+
+pub mod dxf;
+pub use dxf::*;
+
+pub mod gcode;
+pub use gcode::*;
+
+pub mod font;
+pub use font::*;
+
+pub mod geojson;
+pub use geojson::*;
+
+pub mod wkt;
+pub use wkt::*;
+
+pub mod pipeline;
+pub use pipeline::*;
+
+pub mod validate;
+pub use validate::*;
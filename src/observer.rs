@@ -0,0 +1,134 @@
+use std::{collections::HashMap, time::Duration};
+
+/// Sent to an `Observer` before a query runs.
+#[derive(Debug, Clone)]
+pub struct QueryStart {
+    pub name: String,
+    pub parameters: String,
+    /// How many container queries, like `LoopOver`, this query is nested in.
+    pub depth: usize,
+    /// The number of sub-groups in every group.
+    pub group_sizes: HashMap<String, usize>,
+}
+
+/// Sent to an `Observer` after a query ran.
+#[derive(Debug, Clone)]
+pub struct QueryEnd {
+    pub name: String,
+    pub parameters: String,
+    pub depth: usize,
+    pub elapsed: Duration,
+    /// How many times a compiled expression was called, nested queries included.
+    pub evaluations: usize,
+    pub group_sizes_before: HashMap<String, usize>,
+    pub group_sizes_after: HashMap<String, usize>,
+    pub error: Option<String>,
+}
+
+/// Receives an event before and after every query, see `Data::set_observer`.
+pub trait Observer {
+    fn query_start(&mut self, _event: &QueryStart) {}
+
+    fn query_end(&mut self, _event: &QueryEnd) {}
+}
+
+impl std::fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+/// Prints a line to stderr after every query.
+#[derive(Debug, Clone, Default)]
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn query_end(&mut self, event: &QueryEnd) {
+        eprintln!(
+            "{}{} took {:?} with {} evaluations{}",
+            "  ".repeat(event.depth),
+            event.name,
+            event.elapsed,
+            event.evaluations,
+            match &event.error {
+                Some(err) => format!(", failed: {}", err),
+                None => String::new(),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use geo::polygon;
+
+    use crate::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<QueryEnd>>>,
+    }
+
+    impl Observer for Recorder {
+        fn query_end(&mut self, event: &QueryEnd) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![
+            polygon! {(0.0, 0.0).into()},
+            polygon! {(1.0, 0.0).into()},
+        ]);
+
+        let recorder = Recorder::default();
+        let events = recorder.events.clone();
+        data.set_observer(Box::new(recorder));
+
+        let queries = vec![Instruction::LoopOver(LoopOver {
+            get_group: "main".into(),
+            iterator_name: "iter".into(),
+            instructions: vec![Instruction::Filter(Filter {
+                set_group: "output".into(),
+                get_group: "iter".into(),
+                code: "true".into(),
+            })],
+            index_name: None,
+            collect: None,
+            parallel: false,
+        })];
+
+        let Instruction::LoopOver(loop_over) = queries[0].clone() else {
+            unreachable!()
+        };
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let events = events.lock().unwrap();
+        let names = events
+            .iter()
+            .map(|event| (event.name.as_str(), event.depth))
+            .collect::<Vec<(&str, usize)>>();
+        assert_eq!(names, vec![("Filter", 1), ("Filter", 1), ("LoopOver", 0)]);
+
+        // Parameters are the JSON of the query, nested instructions included
+        let Instruction::Filter(filter) = &loop_over.instructions[0] else {
+            unreachable!()
+        };
+        assert_eq!(events[0].parameters, serde_json::to_string(filter).unwrap());
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&events[2].parameters).unwrap(),
+            serde_json::to_value(&loop_over).unwrap()
+        );
+
+        let loop_over = &events[2];
+        assert_eq!(loop_over.evaluations, 2);
+        assert_eq!(loop_over.group_sizes_before.get("output"), None);
+        assert_eq!(loop_over.group_sizes_after["output"], 1);
+    }
+}
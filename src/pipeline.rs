@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Any of the built in queries, so a list of them can be stored in a pipeline file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Instruction {
+    GroupBy(GroupBy),
+    Filter(Filter),
+    Sort(Sort),
+    Transformation(Transformation),
+    Kerning(Kerning),
+    RecomputeDepths(RecomputeDepths),
+    GroupUnion(GroupUnion),
+    GroupIntersect(GroupIntersect),
+    GroupDifference(GroupDifference),
+    Flatten(Flatten),
+    Explode(Explode),
+    Concat(Concat),
+    Map(Map),
+    Let(Let),
+    If(If<Instruction>),
+    While(While<Instruction>),
+    Call(Call),
+    LoopOver(LoopOver<Instruction>),
+    Checkpoint(Checkpoint),
+    Rollback(Rollback),
+    Text(Text),
+}
+
+impl Instruction {
+    fn as_query(&self) -> &dyn Query {
+        match self {
+            Instruction::GroupBy(query) => query,
+            Instruction::Filter(query) => query,
+            Instruction::Sort(query) => query,
+            Instruction::Transformation(query) => query,
+            Instruction::Kerning(query) => query,
+            Instruction::RecomputeDepths(query) => query,
+            Instruction::GroupUnion(query) => query,
+            Instruction::GroupIntersect(query) => query,
+            Instruction::GroupDifference(query) => query,
+            Instruction::Flatten(query) => query,
+            Instruction::Explode(query) => query,
+            Instruction::Concat(query) => query,
+            Instruction::Map(query) => query,
+            Instruction::Let(query) => query,
+            Instruction::If(query) => query,
+            Instruction::While(query) => query,
+            Instruction::Call(query) => query,
+            Instruction::LoopOver(query) => query,
+            Instruction::Checkpoint(query) => query,
+            Instruction::Rollback(query) => query,
+            Instruction::Text(query) => query,
+        }
+    }
+
+    fn as_query_mut(&mut self) -> &mut dyn Query {
+        match self {
+            Instruction::GroupBy(query) => query,
+            Instruction::Filter(query) => query,
+            Instruction::Sort(query) => query,
+            Instruction::Transformation(query) => query,
+            Instruction::Kerning(query) => query,
+            Instruction::RecomputeDepths(query) => query,
+            Instruction::GroupUnion(query) => query,
+            Instruction::GroupIntersect(query) => query,
+            Instruction::GroupDifference(query) => query,
+            Instruction::Flatten(query) => query,
+            Instruction::Explode(query) => query,
+            Instruction::Concat(query) => query,
+            Instruction::Map(query) => query,
+            Instruction::Let(query) => query,
+            Instruction::If(query) => query,
+            Instruction::While(query) => query,
+            Instruction::Call(query) => query,
+            Instruction::LoopOver(query) => query,
+            Instruction::Checkpoint(query) => query,
+            Instruction::Rollback(query) => query,
+            Instruction::Text(query) => query,
+        }
+    }
+}
+
+impl Query for Instruction {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        self.as_query_mut().query(data)
+    }
+
+    fn fork(&self) -> Option<Box<dyn Query + Send>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn instruction(&self) -> Option<&Instruction> {
+        Some(self)
+    }
+
+    fn name(&self) -> String {
+        self.as_query().name()
+    }
+
+    fn describe(&self) -> String {
+        self.as_query().describe()
+    }
+}
+
+/// A named sub-pipeline. `params` are the group names its instructions use for the groups
+/// a `Call` binds; every other group it sets stays local to the call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Macro {
+    pub params: Vec<String>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// A list of queries together with the parameters and macros they use.
+///
+/// Every entry in `params` is set with `Data::set_param` and every entry in `macros` is
+/// defined with `Data::define_macro` before the queries run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pipeline {
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub macros: HashMap<String, Macro>,
+    pub queries: Vec<Instruction>,
+}
+
+impl Pipeline {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|err| format!("Could not parse pipeline: {}", err))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Could not write pipeline: {}", err))
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+        Self::from_json(&json)
+    }
+
+    /// Overrides a parameter, for example from the command line.
+    pub fn set_param(&mut self, name: &str, value: serde_json::Value) {
+        self.params.insert(name.to_string(), value);
+    }
+
+    pub fn run(&self, data: &mut Data) -> Result<(), String> {
+        for (name, value) in &self.params {
+            data.set_param(name, value.clone())?;
+        }
+        for (name, definition) in &self.macros {
+            data.define_macro(name, definition.clone());
+        }
+
+        data.query(self.queries.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut pipeline = Pipeline::from_json(
+            r#"{
+                "params": { "min_len": 5 },
+                "queries": [
+                    { "type": "Let", "name": "limit", "code": "min_len - 4" },
+                    {
+                        "type": "Filter",
+                        "set_group": "output",
+                        "get_group": "main",
+                        "code": "len('main', i) >= limit"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+        if let Err(err) = pipeline.run(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        assert_eq!(data.groups.lock().unwrap()["output"], vec![vec![0]]);
+        assert_eq!(data.params["limit"], 1);
+
+        pipeline.set_param("min_len", 6.into());
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+        if let Err(err) = pipeline.run(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        assert_eq!(
+            data.groups.lock().unwrap()["output"],
+            Vec::<Vec<usize>>::new()
+        );
+    }
+}
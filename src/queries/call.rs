@@ -0,0 +1,134 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Runs the macro `name` in a scope of its own.
+///
+/// `bind` maps each of the macro's params to a group of the caller. The bound groups are
+/// copied into the scope before the macro runs and copied back out after it finishes, while
+/// any other group the macro sets is dropped with the scope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Call {
+    pub name: String,
+    pub bind: HashMap<String, String>,
+}
+
+impl Query for Call {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let Some(definition) = data.macros.get(&self.name).cloned() else {
+            return Err(format!("Could not find macro '{}'.", self.name));
+        };
+
+        for param in &definition.params {
+            if !self.bind.contains_key(param) {
+                return Err(format!("Call to '{}' doesn't bind '{}'.", self.name, param));
+            }
+        }
+        for param in self.bind.keys() {
+            if !definition.params.contains(param) {
+                return Err(format!("Macro '{}' has no param '{}'.", self.name, param));
+            }
+        }
+
+        let (caller_groups, caller_attributes) = {
+            let mut groups = data.groups.lock().unwrap();
+            let mut attributes = data.attributes.lock().unwrap();
+
+            let caller_groups = std::mem::take(&mut *groups);
+            let caller_attributes = std::mem::take(&mut *attributes);
+            for (param, name) in &self.bind {
+                if let Some(group) = caller_groups.get(name) {
+                    Arc::make_mut(&mut groups).insert(param.clone(), group.clone());
+                }
+                if let Some(group_attributes) = caller_attributes.get(name) {
+                    Arc::make_mut(&mut attributes).insert(param.clone(), group_attributes.clone());
+                }
+            }
+
+            (caller_groups, caller_attributes)
+        };
+
+        let mut result = Ok(());
+        for mut instruction in definition.instructions {
+            result = data.run(&mut instruction);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let mut groups = data.groups.lock().unwrap();
+        let mut attributes = data.attributes.lock().unwrap();
+
+        let scope_groups = std::mem::replace(&mut *groups, caller_groups);
+        let scope_attributes = std::mem::replace(&mut *attributes, caller_attributes);
+        if result.is_ok() {
+            for (param, name) in &self.bind {
+                if let Some(group) = scope_groups.get(param) {
+                    Arc::make_mut(&mut groups).insert(name.clone(), group.clone());
+                }
+                if let Some(group_attributes) = scope_attributes.get(param) {
+                    Arc::make_mut(&mut attributes).insert(name.clone(), group_attributes.clone());
+                }
+            }
+        }
+
+        result.map_err(|err| format!("In macro '{}': {}", self.name, err))
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        data.define_macro(
+            "copy_twice",
+            Macro {
+                params: vec!["in".into(), "out".into()],
+                instructions: vec![
+                    Instruction::Filter(Filter {
+                        set_group: "temporary".into(),
+                        get_group: "in".into(),
+                        code: "true".into(),
+                    }),
+                    Instruction::Concat(Concat {
+                        set_group: "out".into(),
+                        get_groups: vec!["temporary".into(), "in".into()],
+                    }),
+                ],
+            },
+        );
+
+        let queries = vec![Instruction::Call(Call {
+            name: "copy_twice".into(),
+            bind: [
+                ("in".to_string(), "main".to_string()),
+                ("out".to_string(), "copied".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        })];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let groups = data.groups.lock().unwrap();
+        assert_eq!(groups["copied"], vec![vec![0], vec![0]]);
+        assert_eq!(groups["main"], vec![vec![0]]);
+        assert!(!groups.contains_key("temporary"));
+        assert!(!groups.contains_key("in"));
+    }
+}
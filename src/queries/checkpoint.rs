@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Saves a snapshot of the data as `name` for a later `Rollback`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub name: String,
+}
+
+impl Query for Checkpoint {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let snapshot = data.snapshot();
+        data.checkpoints.insert(self.name.clone(), snapshot);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Restores the checkpoint `name` unless `check` is truthy. An empty `check` always
+/// rolls back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rollback {
+    pub name: String,
+    #[serde(default)]
+    pub check: String,
+}
+
+impl Query for Rollback {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let Some(snapshot) = data.checkpoints.get(&self.name).cloned() else {
+            return Err(format!("Could not find checkpoint '{}'.", self.name));
+        };
+
+        if !self.check.trim().is_empty() {
+            let code = data.compile(&self.check, &[])?;
+            let value = code
+                .call(&[], &mut data.context)
+                .map_err(|err| format!("Check '{}' failed: {}", self.check, err))?;
+            if value.to_boolean() {
+                return Ok(());
+            }
+        }
+
+        data.restore(snapshot)
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    fn transformation() -> Instruction {
+        Instruction::Transformation(Transformation {
+            set_group: "moved".into(),
+            get_group: "main".into(),
+            transformation: [
+                "1.0".into(),
+                "0.0".into(),
+                "offset".into(),
+                "0.0".into(),
+                "1.0".into(),
+                "0.0".into(),
+            ],
+        })
+    }
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![polygon![
+            (x: 0.0, y: 0.0),
+            (x: 1.0, y: 0.0),
+            (x: 1.0, y: 1.0),
+            (x: 0.0, y: 1.0),
+        ]]);
+
+        let queries = vec![
+            Instruction::Checkpoint(Checkpoint {
+                name: "before".into(),
+            }),
+            Instruction::Let(Let {
+                name: "offset".into(),
+                code: "10".into(),
+            }),
+            transformation(),
+            Instruction::Rollback(Rollback {
+                name: "before".into(),
+                check: "frame('moved', 0, 0).min_x < 5".into(),
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        assert_eq!(data.shapes.lock().unwrap().len(), 1);
+        assert_eq!(data.depths.lock().unwrap().len(), 1);
+        assert!(!data.groups.lock().unwrap().contains_key("moved"));
+        assert!(!data.params.contains_key("offset"));
+
+        let queries = vec![
+            Instruction::Let(Let {
+                name: "offset".into(),
+                code: "1".into(),
+            }),
+            Instruction::Checkpoint(Checkpoint {
+                name: "before".into(),
+            }),
+            transformation(),
+            Instruction::Rollback(Rollback {
+                name: "before".into(),
+                check: "frame('moved', 0, 0).min_x < 5".into(),
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        assert_eq!(data.shapes.lock().unwrap().len(), 2);
+        assert!(data.groups.lock().unwrap().contains_key("moved"));
+    }
+
+    #[test]
+    fn newer_checkpoints() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+        data.set_param("offset", 10.into()).unwrap();
+
+        let queries = vec![
+            Instruction::Checkpoint(Checkpoint { name: "a".into() }),
+            transformation(),
+            Instruction::Checkpoint(Checkpoint { name: "b".into() }),
+            Instruction::Rollback(Rollback {
+                name: "a".into(),
+                check: String::new(),
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        // The shapes `b` kept are gone, so it goes with them
+        assert!(data.checkpoints.contains_key("a"));
+        assert!(!data.checkpoints.contains_key("b"));
+        let mut rollback = Rollback {
+            name: "b".into(),
+            check: String::new(),
+        };
+        assert!(rollback.query(&mut data).is_err());
+
+        // A checkpoint shares the groups and attributes until they change
+        assert_eq!(
+            std::sync::Arc::strong_count(&data.groups.lock().unwrap()),
+            2
+        );
+        data.set_group("other", Vec::new());
+        assert_eq!(
+            std::sync::Arc::strong_count(&data.groups.lock().unwrap()),
+            1
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+fn evaluate_condition(condition: &str, data: &mut Data) -> Result<bool, String> {
+    let code = data.compile(condition, &[])?;
+    let value = code
+        .call(&[], &mut data.context)
+        .map_err(|err| format!("Condition '{}' failed: {}", condition, err))?;
+
+    Ok(value.to_boolean())
+}
+
+/// Runs `then` when `condition` is truthy and `otherwise` when it isn't.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct If<T = Box<dyn Query>>
+where
+    T: Query,
+{
+    pub condition: String,
+    pub then: Vec<T>,
+    #[serde(default, rename = "else")]
+    pub otherwise: Vec<T>,
+}
+
+impl<T: Query> Query for If<T> {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let instructions = if evaluate_condition(&self.condition, data)? {
+            &mut self.then
+        } else {
+            &mut self.otherwise
+        };
+
+        for instruction in instructions {
+            data.run(instruction)?;
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::json!({
+            "condition": self.condition,
+            "then": describe_all(&self.then),
+            "else": describe_all(&self.otherwise),
+        })
+        .to_string()
+    }
+}
+
+/// Runs `instructions` for as long as `condition` is truthy.
+///
+/// Running them more than `max_iterations` times is an error, so a condition that never
+/// turns false can't hang a pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct While<T = Box<dyn Query>>
+where
+    T: Query,
+{
+    pub condition: String,
+    pub instructions: Vec<T>,
+    pub max_iterations: usize,
+}
+
+impl<T: Query> Query for While<T> {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let mut iterations = 0;
+        while evaluate_condition(&self.condition, data)? {
+            if iterations == self.max_iterations {
+                return Err(format!(
+                    "While '{}' is still true after {} iterations.",
+                    self.condition, self.max_iterations
+                ));
+            }
+            iterations += 1;
+
+            for instruction in &mut self.instructions {
+                data.run(instruction)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::json!({
+            "condition": self.condition,
+            "instructions": describe_all(&self.instructions),
+            "max_iterations": self.max_iterations,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        let mut queries: Vec<Box<dyn Query>> = vec![
+            Box::from(If {
+                condition: "len('outside_box') > 0".into(),
+                then: vec![Box::from(Filter {
+                    set_group: "output".into(),
+                    get_group: "main".into(),
+                    code: "false".into(),
+                }) as Box<dyn Query>],
+                otherwise: vec![Box::from(Filter {
+                    set_group: "output".into(),
+                    get_group: "main".into(),
+                    code: "true".into(),
+                }) as Box<dyn Query>],
+            }),
+            Box::from(While {
+                condition: "len('output') < 3".into(),
+                instructions: vec![Box::from(Concat {
+                    set_group: "output".into(),
+                    get_groups: vec!["output".into(), "main".into()],
+                }) as Box<dyn Query>],
+                max_iterations: 10,
+            }),
+        ];
+
+        for query in &mut queries {
+            if let Err(err) = query.query(&mut data) {
+                println!("Error: {}", err);
+                assert!(false);
+            }
+        }
+
+        let groups = data.groups.lock().unwrap();
+        assert_eq!(groups["output"], vec![vec![0], vec![0], vec![0]]);
+    }
+
+    #[test]
+    fn max_iterations() {
+        let mut data = Data::from(vec![polygon! {(0.0, 0.0).into()}]);
+
+        let mut query: While = While {
+            condition: "true".into(),
+            instructions: Vec::new(),
+            max_iterations: 5,
+        };
+
+        assert!(query.query(&mut data).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Recomputes the containment depth of every shape in `get_group` from its current geometry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecomputeDepths {
+    pub get_group: String,
+}
+
+impl Query for RecomputeDepths {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let shapes_indexes = {
+            let groups = data.groups.lock().unwrap();
+            let Some(shapes_indexes) = groups.get(&self.get_group) else {
+                return Err(format!("Could not find '{}' in groups.", self.get_group));
+            };
+            shapes_indexes
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<usize>>()
+        };
+
+        data.recompute_depths(&shapes_indexes);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    fn square(min: f64, max: f64) -> geo::Polygon {
+        polygon![
+            (x: min, y: min),
+            (x: max, y: min),
+            (x: max, y: max),
+            (x: min, y: max),
+        ]
+    }
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![square(0.0, 10.0), square(1.0, 2.0)]);
+
+        let mut transformation = Transformation {
+            set_group: "moved".into(),
+            get_group: "main".into(),
+            transformation: [
+                "1.0".into(),
+                "0.0".into(),
+                "20.0".into(),
+                "0.0".into(),
+                "1.0".into(),
+                "0.0".into(),
+            ],
+        };
+
+        if let Err(err) = transformation.query(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        // Derived shapes inherit the depth of their source
+        assert_eq!(*data.depths.lock().unwrap(), vec![0, 1, 0, 1]);
+
+        let mut filter = Filter {
+            set_group: "moved_inner".into(),
+            get_group: "moved".into(),
+            code: "depth(group_index('moved', i, 0)) == 1".into(),
+        };
+
+        if let Err(err) = filter.query(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        assert_eq!(data.groups.lock().unwrap()["moved_inner"], vec![vec![3]]);
+
+        let mut recompute = RecomputeDepths {
+            get_group: "moved_inner".into(),
+        };
+
+        if let Err(err) = recompute.query(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        // On its own the inner square isn't inside anything
+        assert_eq!(*data.depths.lock().unwrap(), vec![0, 1, 0, 0]);
+    }
+}
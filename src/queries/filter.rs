@@ -1,4 +1,4 @@
-use boa_engine::{JsValue, Source, js_string, property::Attribute};
+use boa_engine::JsValue;
 use serde::{Deserialize, Serialize};
 
 use crate::*;
@@ -20,27 +20,31 @@ impl Query for Filter {
             shapes_indexes.clone()
         };
 
-        let new_group = shapes_indexes
-            .iter()
-            .enumerate()
-            .filter(|(index, _)| {
-                data.context
-                    .register_global_property(js_string!("i"), *index, Attribute::all())
-                    .expect("property shouldn't exist");
-
-                match data.context.eval(Source::from_bytes(&self.code)) {
-                    Ok(JsValue::Boolean(value)) => value,
-                    _ => false,
-                }
-            })
-            .map(|(_, shapes_indexes)| shapes_indexes.clone())
-            .collect::<Vec<Vec<usize>>>();
-
-        let mut groups = data.groups.lock().unwrap();
-        groups.insert(self.set_group.clone(), new_group);
+        let code = data.compile(&self.code, &["i"])?;
+        data.trace_query(&self.name(), &self.get_group, &self.set_group);
+
+        let mut new_group = Vec::new();
+        for (index, shapes_indexes) in shapes_indexes.iter().enumerate() {
+            let keep = match code.call(&[JsValue::new(index)], &mut data.context) {
+                Ok(JsValue::Boolean(value)) => value,
+                Ok(_) => false,
+                Err(err) => return Err(format!("Could not evaluate '{}': {}", self.code, err)),
+            };
+            data.trace_element(index, shapes_indexes, keep.then_some(new_group.len()), None);
+
+            if keep {
+                new_group.push(shapes_indexes.clone());
+            }
+        }
+
+        data.set_group(&self.set_group, new_group);
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+fn get_group(data: &Data, name: &str) -> Result<Vec<Vec<usize>>, String> {
+    let groups = data.groups.lock().unwrap();
+    let Some(shapes_indexes) = groups.get(name) else {
+        return Err(format!("Could not find '{}' in groups.", name));
+    };
+    Ok(shapes_indexes.clone())
+}
+
+/// Keeps only the shapes of each sub-group that pass `keep`, dropping sub-groups left empty.
+fn retain_shapes(group: Vec<Vec<usize>>, mut keep: impl FnMut(usize) -> bool) -> Vec<Vec<usize>> {
+    group
+        .into_iter()
+        .map(|shapes| {
+            shapes
+                .into_iter()
+                .filter(|index| keep(*index))
+                .collect::<Vec<usize>>()
+        })
+        .filter(|shapes| !shapes.is_empty())
+        .collect()
+}
+
+/// The sub-groups of `get_group` followed by the sub-groups of `other_group`.
+/// Shapes of `other_group` that are already in `get_group` are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupUnion {
+    pub set_group: String,
+    pub get_group: String,
+    pub other_group: String,
+}
+
+impl Query for GroupUnion {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let mut new_group = get_group(data, &self.get_group)?;
+        let other_group = get_group(data, &self.other_group)?;
+
+        let mut seen = new_group
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<HashSet<usize>>();
+        new_group.append(&mut retain_shapes(other_group, |index| seen.insert(index)));
+
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// The shapes of each sub-group of `get_group` that are also in `other_group`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupIntersect {
+    pub set_group: String,
+    pub get_group: String,
+    pub other_group: String,
+}
+
+impl Query for GroupIntersect {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let shapes_indexes = get_group(data, &self.get_group)?;
+        let other = get_group(data, &self.other_group)?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<usize>>();
+
+        let new_group = retain_shapes(shapes_indexes, |index| other.contains(&index));
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// The shapes of each sub-group of `get_group` that are not in `other_group`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupDifference {
+    pub set_group: String,
+    pub get_group: String,
+    pub other_group: String,
+}
+
+impl Query for GroupDifference {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let shapes_indexes = get_group(data, &self.get_group)?;
+        let other = get_group(data, &self.other_group)?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<usize>>();
+
+        let new_group = retain_shapes(shapes_indexes, |index| !other.contains(&index));
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Merges every sub-group of `get_group` into a single sub-group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Flatten {
+    pub set_group: String,
+    pub get_group: String,
+}
+
+impl Query for Flatten {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let shapes_indexes = get_group(data, &self.get_group)?;
+
+        let mut seen = HashSet::new();
+        let new_group = retain_shapes(vec![shapes_indexes.concat()], |index| seen.insert(index));
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Splits `get_group` so every shape is in a sub-group of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Explode {
+    pub set_group: String,
+    pub get_group: String,
+}
+
+impl Query for Explode {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let shapes_indexes = get_group(data, &self.get_group)?;
+
+        let new_group = shapes_indexes
+            .into_iter()
+            .flatten()
+            .map(|index| vec![index])
+            .collect();
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Appends the sub-groups of every group in `get_groups`, in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Concat {
+    pub set_group: String,
+    pub get_groups: Vec<String>,
+}
+
+impl Query for Concat {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let mut new_group = Vec::new();
+        for name in &self.get_groups {
+            new_group.append(&mut get_group(data, name)?);
+        }
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    fn data() -> Data {
+        let data = Data::from(vec![
+            polygon! {(0.0, 0.0).into()},
+            polygon! {(1.0, 0.0).into()},
+            polygon! {(2.0, 0.0).into()},
+        ]);
+        data.set_group("a", vec![vec![0, 1], vec![2]]);
+        data.set_group("b", vec![vec![1, 2]]);
+        data
+    }
+
+    #[test]
+    fn it_works() {
+        let mut data = data();
+
+        let queries: Vec<Box<dyn Query>> = vec![
+            Box::from(GroupUnion {
+                set_group: "union".into(),
+                get_group: "b".into(),
+                other_group: "a".into(),
+            }),
+            Box::from(GroupIntersect {
+                set_group: "intersect".into(),
+                get_group: "a".into(),
+                other_group: "b".into(),
+            }),
+            Box::from(GroupDifference {
+                set_group: "difference".into(),
+                get_group: "a".into(),
+                other_group: "b".into(),
+            }),
+            Box::from(Flatten {
+                set_group: "flatten".into(),
+                get_group: "a".into(),
+            }),
+            Box::from(Explode {
+                set_group: "explode".into(),
+                get_group: "a".into(),
+            }),
+            Box::from(Concat {
+                set_group: "concat".into(),
+                get_groups: vec!["a".into(), "b".into()],
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let groups = data.groups.lock().unwrap();
+
+        assert_eq!(groups["union"], vec![vec![1, 2], vec![0]]);
+        assert_eq!(groups["intersect"], vec![vec![1], vec![2]]);
+        assert_eq!(groups["difference"], vec![vec![0]]);
+        assert_eq!(groups["flatten"], vec![vec![0, 1, 2]]);
+        assert_eq!(groups["explode"], vec![vec![0], vec![1], vec![2]]);
+        assert_eq!(groups["concat"], vec![vec![0, 1], vec![2], vec![1, 2]]);
+    }
+
+    #[test]
+    fn missing_group() {
+        let mut data = data();
+
+        let mut difference = GroupDifference {
+            set_group: "difference".into(),
+            get_group: "a".into(),
+            other_group: "missing".into(),
+        };
+
+        assert!(difference.query(&mut data).is_err());
+    }
+}
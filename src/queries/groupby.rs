@@ -1,4 +1,4 @@
-use boa_engine::{JsValue, Source, js_string, property::Attribute};
+use boa_engine::JsValue;
 use serde::{Deserialize, Serialize};
 
 use crate::*;
@@ -13,61 +13,55 @@ pub struct GroupBy {
 impl Query for GroupBy {
     fn query(&mut self, data: &mut Data) -> Result<(), String> {
         let shapes_indexes = {
-            let mut groups = data.groups.lock().unwrap();
+            let groups = data.groups.lock().unwrap();
             let Some(shapes_indexes) = groups.get(&self.get_group) else {
                 return Err(format!("Could not find '{}' in groups.", self.get_group));
             };
-
-            if shapes_indexes.is_empty() {
-                groups.insert(self.set_group.clone(), Vec::new());
-                return Ok(());
-            }
-
             shapes_indexes.clone()
         };
+        if shapes_indexes.is_empty() {
+            data.set_group(&self.set_group, Vec::new());
+            return Ok(());
+        }
+
+        let code = data.compile(&self.code, &["i", "j"])?;
+        data.trace_query(&self.name(), &self.get_group, &self.set_group);
 
         let mut new_groups = Vec::new();
         new_groups.push(shapes_indexes[0].clone());
+        data.trace_element(0, &shapes_indexes[0], Some(0), None);
 
-        {
-            let mut groups = data.groups.lock().unwrap();
-            groups.insert(self.set_group.clone(), new_groups.clone());
-        }
+        data.set_group(&self.set_group, new_groups.clone());
 
         'outer: for i in 1..shapes_indexes.len() {
-            data.context
-                .register_global_property(js_string!("i"), i, Attribute::all())
-                .expect("property shouldn't exist");
-
             for j in 0..new_groups.len() {
-                data.context
-                    .register_global_property(js_string!("j"), j, Attribute::all())
-                    .expect("property shouldn't exist");
-                if let Ok(JsValue::Boolean(value)) =
-                    data.context.eval(Source::from_bytes(&self.code))
+                let value = match code.call(&[JsValue::new(i), JsValue::new(j)], &mut data.context)
                 {
-                    if value {
-                        new_groups[j].append(&mut shapes_indexes[i].clone());
-
-                        let mut groups = data.groups.lock().unwrap();
-                        groups.insert(self.set_group.clone(), new_groups.clone());
-                        continue 'outer;
+                    Ok(JsValue::Boolean(value)) => value,
+                    Ok(_) => false,
+                    Err(err) => {
+                        return Err(format!("Could not evaluate '{}': {}", self.code, err));
                     }
+                };
+                if value {
+                    new_groups[j].append(&mut shapes_indexes[i].clone());
+                    data.trace_element(i, &shapes_indexes[i], Some(j), None);
+                    data.set_group(&self.set_group, new_groups.clone());
+                    continue 'outer;
                 }
             }
+            data.trace_element(i, &shapes_indexes[i], Some(new_groups.len()), None);
             new_groups.push(shapes_indexes[i].clone());
-            {
-                let mut groups = data.groups.lock().unwrap();
-                groups.insert(self.set_group.clone(), new_groups.clone());
-            }
-        }
-        {
-            let mut groups = data.groups.lock().unwrap();
-            groups.insert(self.set_group.clone(), new_groups);
+            data.set_group(&self.set_group, new_groups.clone());
         }
+        data.set_group(&self.set_group, new_groups);
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
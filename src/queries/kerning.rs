@@ -1,4 +1,4 @@
-use boa_engine::{Context, JsValue, Source, js_string, property::Attribute};
+use boa_engine::{Context, JsValue};
 use geo::{
     BoundingRect, Centroid, Contains, Distance, Euclidean, MultiPolygon, Polygon, Translate,
 };
@@ -42,11 +42,13 @@ enum Direction {
     Center,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn kern_group(
     shapes_to_kern: &mut Vec<Polygon>,
     epsilon: f64,
     space: f64,
-    respect_space: &str,
+    respect_space: &Expression,
+    group_index: usize,
     is_horizontal: bool,
     direction: Direction,
     context: &mut Context,
@@ -113,11 +115,9 @@ fn kern_group(
 
         // Check to see if we have to respect the distance for the rest of the letters
         if distances_kerened > 0. && i + 1 < shapes_to_kern.len() {
-            context
-                .register_global_property(js_string!("j"), i + 1, Attribute::all())
-                .expect("property shouldn't exist");
-
-            if let Ok(JsValue::Boolean(value)) = context.eval(Source::from_bytes(respect_space)) {
+            if let Ok(JsValue::Boolean(value)) =
+                respect_space.call(&[JsValue::new(group_index), JsValue::new(i + 1)], context)
+            {
                 if value {
                     for j in i + 1..shapes_to_kern.len() {
                         shapes_to_kern[j]
@@ -164,19 +164,24 @@ fn kern_group(
 impl Query for Kerning {
     fn query(&mut self, data: &mut Data) -> Result<(), String> {
         let mut space: f64 = 0.0;
-        if let Ok(value) = data.context.eval(Source::from_bytes(&self.space)) {
+        let space_code = data.compile(&self.space, &[])?;
+        if let Ok(value) = space_code.call(&[], &mut data.context) {
             if let Ok(value) = value.to_f32(&mut data.context) {
                 space = value as f64;
             }
         }
 
         let mut epsilon: f64 = 0.0;
-        if let Ok(value) = data.context.eval(Source::from_bytes(&self.epsilon)) {
+        let epsilon_code = data.compile(&self.epsilon, &[])?;
+        if let Ok(value) = epsilon_code.call(&[], &mut data.context) {
             if let Ok(value) = value.to_f32(&mut data.context) {
                 epsilon = value as f64;
             }
         }
 
+        let respect_space = data.compile(&self.respect_space, &["i", "j"])?;
+        data.trace_query(&self.name(), &self.get_group, &self.set_group);
+
         let (kerned_group, borders_group, mut inner_shapes) = {
             let groups = data.groups.lock().unwrap();
             let Some(shapes_indexes) = groups.get(&self.get_group) else {
@@ -206,6 +211,7 @@ impl Query for Kerning {
         };
 
         let shapes = { data.shapes.lock().unwrap().clone() };
+        let depths = { data.depths.lock().unwrap().clone() };
 
         let mut new_group = Vec::new();
 
@@ -400,37 +406,48 @@ impl Query for Kerning {
             }
 
             for mut node in inside {
-                data.context
-                    .register_global_property(js_string!("i"), node.value.0, Attribute::all())
-                    .expect("property shouldn't exist");
-
                 if node.value.1.len() < 2 {
+                    data.trace_element(node.value.0, &kerned_group[node.value.0], None, None);
                     continue;
                 }
                 let is_horizontal = node.value.3;
+                // Sort the indexes along with the shapes so their depths and trace stay paired
+                let mut shapes_with_depths: Vec<(Polygon, usize)> = node
+                    .value
+                    .1
+                    .drain(..)
+                    .zip(kerned_group[node.value.0].iter().copied())
+                    .collect();
                 if is_horizontal {
-                    node.value.1.sort_by(|l, r| {
-                        l.bounding_rect()
+                    shapes_with_depths.sort_by(|l, r| {
+                        l.0.bounding_rect()
                             .unwrap()
                             .min()
                             .x
-                            .partial_cmp(&r.bounding_rect().unwrap().min().x)
+                            .partial_cmp(&r.0.bounding_rect().unwrap().min().x)
                             .unwrap()
                     });
                 } else {
-                    node.value.1.sort_by(|l, r| {
-                        l.bounding_rect()
+                    shapes_with_depths.sort_by(|l, r| {
+                        l.0.bounding_rect()
                             .unwrap()
                             .min()
                             .y
-                            .partial_cmp(&r.bounding_rect().unwrap().min().y)
+                            .partial_cmp(&r.0.bounding_rect().unwrap().min().y)
                             .unwrap()
                     });
                 }
+                let (sorted_shapes, sorted_indexes): (Vec<Polygon>, Vec<usize>) =
+                    shapes_with_depths.into_iter().unzip();
+                let shape_depths: Vec<usize> =
+                    sorted_indexes.iter().map(|index| depths[*index]).collect();
+                node.value.1 = sorted_shapes;
 
                 let Some(direction) = node.value.4 else {
+                    data.trace_element(node.value.0, &kerned_group[node.value.0], None, None);
                     continue;
                 };
+                let direction_name = format!("{:?}", direction);
 
                 let original_shapes_to_kern = node.value.1.clone();
 
@@ -438,12 +455,32 @@ impl Query for Kerning {
                     &mut node.value.1,
                     epsilon,
                     space,
-                    &self.respect_space,
+                    &respect_space,
+                    node.value.0,
                     node.value.3,
                     direction,
                     &mut data.context,
                 );
 
+                data.trace_element(
+                    node.value.0,
+                    &sorted_indexes,
+                    Some(new_group.len()),
+                    Some(KerningStep {
+                        horizontal: node.value.3,
+                        direction: direction_name,
+                        offsets: original_shapes_to_kern
+                            .iter()
+                            .zip(&node.value.1)
+                            .map(|(original, kerned)| {
+                                let offset = kerned.bounding_rect().unwrap().center()
+                                    - original.bounding_rect().unwrap().center();
+                                [offset.x, offset.y]
+                            })
+                            .collect(),
+                    }),
+                );
+
                 let mut new_inner_shapes_additions = Vec::new();
                 'inner_shapes_loop: for inner_shape_index in &inner_shapes {
                     let inner_shape_index = *inner_shape_index;
@@ -457,6 +494,7 @@ impl Query for Kerning {
                             new_inner_shapes_additions.push((
                                 inner_shape_index,
                                 shapes[inner_shape_index].translate(dif.x, dif.y),
+                                depths[inner_shape_index],
                             ));
 
                             continue 'inner_shapes_loop;
@@ -464,23 +502,25 @@ impl Query for Kerning {
                     }
                 }
 
-                for (index, shape) in new_inner_shapes_additions {
+                for (index, shape, depth) in new_inner_shapes_additions {
                     inner_shapes.remove(&index);
-                    new_inner_shapes.push(shape);
+                    new_inner_shapes.push((shape, depth));
                 }
 
-                new_group.push(node.value.1);
+                new_group.push((node.value.1, shape_depths));
             }
         }
 
         let mut shapes = data.shapes.lock().unwrap();
+        let mut depths = data.depths.lock().unwrap();
         let mut group_indexes = Vec::new();
-        for group in new_group {
+        for (group, group_depths) in new_group {
             let mut g_index = Vec::new();
 
-            for polygon in group {
+            for (polygon, depth) in group.into_iter().zip(group_depths) {
                 g_index.push(shapes.len());
                 shapes.push(polygon);
+                depths.push(depth);
             }
 
             group_indexes.push(g_index);
@@ -490,20 +530,26 @@ impl Query for Kerning {
         let mut inner_groups = Vec::new();
         let inner_iter = inner_shapes
             .into_iter()
-            .map(|index| shapes[index].clone())
-            .collect::<Vec<Polygon>>()
+            .map(|index| (shapes[index].clone(), depths[index]))
+            .collect::<Vec<(Polygon, usize)>>()
             .into_iter()
             .chain(new_inner_shapes.into_iter());
 
-        for shape in inner_iter {
+        for (shape, depth) in inner_iter {
             inner_groups.push(vec![shapes.len()]);
             shapes.push(shape);
+            depths.push(depth);
         }
 
-        let mut groups = data.groups.lock().unwrap();
-        groups.insert(self.set_group.clone(), group_indexes);
-        groups.insert(self.set_inner_shapes.clone(), inner_groups);
+        drop(shapes);
+        drop(depths);
+        data.set_group(&self.set_group, group_indexes);
+        data.set_group(&self.set_inner_shapes, inner_groups);
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
 }
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Evaluates `code` once and stores the result as the pipeline parameter `name`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Let {
+    pub name: String,
+    pub code: String,
+}
+
+impl Query for Let {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let code = data.compile(&self.code, &[])?;
+        let value = code
+            .call(&[], &mut data.context)
+            .and_then(|value| value.to_json(&mut data.context))
+            .map_err(|err| format!("Let '{}' failed: {}", self.name, err))?;
+
+        data.set_param(&self.name, value)
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
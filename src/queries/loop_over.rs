@@ -1,6 +1,16 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, atomic::Ordering},
+};
+
+use geo::Polygon;
+use serde::{Deserialize, Serialize};
+
 use crate::*;
 
-#[derive(Debug, Clone)]
+/// Runs `instructions` once for every sub-group of `get_group`, with the group named
+/// `iterator_name` holding just that sub-group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoopOver<T = Box<dyn Query>>
 where
     T: Query,
@@ -8,6 +18,27 @@ where
     pub get_group: String,
     pub iterator_name: String,
     pub instructions: Vec<T>,
+    /// Name of a JS global set to the index of the current sub-group.
+    #[serde(default)]
+    pub index_name: Option<String>,
+    #[serde(default)]
+    pub collect: Option<Collect>,
+    /// Runs the iterations on several threads, each with its own JS context. Every
+    /// iteration starts from the state before the loop, and the shapes, groups, depths,
+    /// attributes and params they change are merged back in iteration order, along with
+    /// their evaluations and observer events. Needs instructions that can be forked, such
+    /// as `Instruction`, and that don't read what an earlier iteration wrote, so the result
+    /// is the same as running in order.
+    #[serde(default)]
+    pub parallel: bool,
+}
+
+/// Appends the sub-groups of `get_group` after every iteration and stores them all in
+/// `set_group` once the loop is done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collect {
+    pub set_group: String,
+    pub get_group: String,
 }
 
 impl<T: Query> Query for LoopOver<T> {
@@ -21,16 +52,377 @@ impl<T: Query> Query for LoopOver<T> {
             }
         };
 
+        // The index is only set while the loop runs
+        let previous = self
+            .index_name
+            .clone()
+            .map(|name| (data.params.get(&name).cloned(), name));
+        let result = if self.parallel {
+            self.query_parallel(data, groups)
+        } else {
+            self.query_sequential(data, groups)
+        };
+        match previous {
+            Some((Some(value), name)) => data.set_param(&name, value)?,
+            Some((None, name)) if data.params.contains_key(&name) => data.remove_param(&name)?,
+            _ => {}
+        }
+
+        result
+    }
+
+    fn describe(&self) -> String {
+        serde_json::json!({
+            "get_group": self.get_group,
+            "iterator_name": self.iterator_name,
+            "instructions": describe_all(&self.instructions),
+            "index_name": self.index_name,
+            "collect": self.collect,
+            "parallel": self.parallel,
+        })
+        .to_string()
+    }
+}
+
+/// What one iteration of a parallel loop made. Shape indexes at or past the length of the
+/// shapes before the loop point into `new_shapes`.
+struct Iteration {
+    index: usize,
+    new_shapes: Vec<Polygon>,
+    new_depths: Vec<usize>,
+    /// Depths the iteration changed of the shapes from before the loop.
+    changed_depths: Vec<(usize, usize)>,
+    groups: Vec<(String, Vec<Vec<usize>>)>,
+    attributes: Vec<(String, Option<HashMap<String, Vec<serde_json::Value>>>)>,
+    params: Vec<(String, Option<serde_json::Value>)>,
+    collected: Vec<Vec<usize>>,
+    evaluations: usize,
+    events: Vec<Event>,
+}
+
+/// A query start or end seen by a worker, passed on to the observer of the loop.
+enum Event {
+    Start(QueryStart),
+    End(QueryEnd),
+}
+
+/// Keeps the events of a worker until the loop passes them on in iteration order.
+struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+impl Observer for Recorder {
+    fn query_start(&mut self, event: &QueryStart) {
+        self.0.lock().unwrap().push(Event::Start(event.clone()));
+    }
+
+    fn query_end(&mut self, event: &QueryEnd) {
+        self.0.lock().unwrap().push(Event::End(event.clone()));
+    }
+}
+
+/// The entries of `map` that differ from `base`, with `None` for the ones it no longer has.
+fn changes<V: Clone + PartialEq>(
+    base: &HashMap<String, V>,
+    map: &HashMap<String, V>,
+) -> Vec<(String, Option<V>)> {
+    let mut changes: Vec<(String, Option<V>)> = map
+        .iter()
+        .filter(|(name, value)| base.get(*name) != Some(*value))
+        .map(|(name, value)| (name.clone(), Some(value.clone())))
+        .collect();
+    changes.extend(
+        base.keys()
+            .filter(|name| !map.contains_key(*name))
+            .map(|name| (name.clone(), None)),
+    );
+    changes
+}
+
+impl<T: Query> LoopOver<T> {
+    fn query_sequential(&mut self, data: &mut Data, groups: Vec<Vec<usize>>) -> Result<(), String> {
         let iterator_name = self.iterator_name.clone();
+        let mut collected = Vec::new();
 
-        for group in groups.clone() {
-            {
-                let mut data_groups = data.groups.lock().unwrap();
-                data_groups.insert(iterator_name.clone(), vec![group]);
+        for (index, group) in groups.clone().into_iter().enumerate() {
+            data.set_group(&iterator_name, vec![group]);
+
+            if let Some(index_name) = &self.index_name {
+                data.set_param(index_name, index.into())?;
             }
 
             for instruction in &mut self.instructions {
-                instruction.query(data)?;
+                data.run(instruction)?;
+            }
+
+            if let Some(collect) = &self.collect {
+                let data_groups = data.groups.lock().unwrap();
+                let Some(group) = data_groups.get(&collect.get_group) else {
+                    return Err(format!("Could not find '{}' in groups.", collect.get_group));
+                };
+                collected.extend(group.iter().cloned());
+            }
+        }
+
+        if let Some(collect) = &self.collect {
+            data.set_group(&collect.set_group, collected);
+        }
+
+        Ok(())
+    }
+
+    fn query_parallel(&mut self, data: &mut Data, groups: Vec<Vec<usize>>) -> Result<(), String> {
+        // Iterations only run apart when none of them needs what the one before did
+        let instructions: Option<Vec<&Instruction>> =
+            self.instructions.iter().map(Query::instruction).collect();
+        if let Some(reason) = instructions.and_then(|instructions| {
+            iteration_dependency(
+                instructions,
+                &self.iterator_name,
+                self.index_name.as_deref(),
+                &data.macros,
+            )
+        }) {
+            return Err(format!(
+                "LoopOver can't run in parallel because {}.",
+                reason
+            ));
+        }
+
+        let workers = std::thread::available_parallelism()
+            .map(|workers| workers.get())
+            .unwrap_or(1)
+            .min(groups.len())
+            .max(1);
+
+        let mut forks = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let mut instructions = Vec::with_capacity(self.instructions.len());
+            for instruction in &self.instructions {
+                let Some(instruction) = instruction.fork() else {
+                    return Err(
+                        "LoopOver can only run in parallel when its instructions can be forked."
+                            .to_string(),
+                    );
+                };
+                instructions.push(instruction);
+            }
+            forks.push(instructions);
+        }
+
+        // Workers share the shapes from before the loop and keep the ones they add apart
+        let base_shapes = data.shapes.lock().unwrap().share();
+        let base_depths = data.depths.lock().unwrap().clone();
+        let base_groups = data.groups.lock().unwrap().clone();
+        let base_attributes = data.attributes.lock().unwrap().clone();
+        let base_len = base_shapes.len();
+        let observed = data.observer.is_some();
+        let query_depth = data.query_depth;
+
+        let results = std::thread::scope(|scope| {
+            let handles = forks
+                .into_iter()
+                .enumerate()
+                .map(|(worker, mut instructions)| {
+                    let (base_shapes, base_depths, base_groups, base_attributes, groups) = (
+                        &base_shapes,
+                        &base_depths,
+                        &base_groups,
+                        &base_attributes,
+                        &groups,
+                    );
+                    let params = &data.params;
+                    let macros = &data.macros;
+                    let iterator_name = &self.iterator_name;
+                    let index_name = &self.index_name;
+                    let collect = &self.collect;
+
+                    scope.spawn(move || -> Result<Vec<Iteration>, String> {
+                        let mut worker_data =
+                            Data::from_parts(Vec::new(), base_depths.clone(), HashMap::new());
+                        *worker_data.shapes.lock().unwrap() = Shapes::from(base_shapes.clone());
+                        for (name, value) in params {
+                            worker_data.set_param(name, value.clone())?;
+                        }
+                        worker_data.macros = macros.clone();
+                        worker_data.query_depth = query_depth;
+                        let events = Arc::new(Mutex::new(Vec::new()));
+                        if observed {
+                            worker_data.set_observer(Box::new(Recorder(events.clone())));
+                        }
+
+                        let mut iterations = Vec::new();
+                        for index in (worker..groups.len()).step_by(workers) {
+                            // Every iteration starts from the state before the loop, and the
+                            // depths and shapes are put back once it's recorded
+                            *worker_data.attributes.lock().unwrap() = base_attributes.clone();
+                            *worker_data.groups.lock().unwrap() = base_groups.clone();
+                            for (name, _) in changes(params, &worker_data.params) {
+                                match params.get(&name) {
+                                    Some(value) => worker_data.set_param(&name, value.clone())?,
+                                    None => worker_data.remove_param(&name)?,
+                                }
+                            }
+                            worker_data.set_group(iterator_name, vec![groups[index].clone()]);
+
+                            if let Some(index_name) = index_name {
+                                worker_data.set_param(index_name, index.into())?;
+                            }
+
+                            let evaluations = worker_data.evaluations.load(Ordering::Relaxed);
+                            for instruction in &mut instructions {
+                                worker_data.run(instruction)?;
+                            }
+
+                            let data_groups = worker_data.groups.lock().unwrap();
+                            let collected = match collect {
+                                Some(collect) => {
+                                    let Some(group) = data_groups.get(&collect.get_group) else {
+                                        return Err(format!(
+                                            "Could not find '{}' in groups.",
+                                            collect.get_group
+                                        ));
+                                    };
+                                    group.clone()
+                                }
+                                None => Vec::new(),
+                            };
+
+                            let mut shapes = worker_data.shapes.lock().unwrap();
+                            let mut depths = worker_data.depths.lock().unwrap();
+                            let new_shapes = shapes.added().to_vec();
+                            let new_depths = depths[base_len..].to_vec();
+                            let changed_depths: Vec<(usize, usize)> = depths[..base_len]
+                                .iter()
+                                .zip(base_depths)
+                                .enumerate()
+                                .filter(|(_, (depth, base))| depth != base)
+                                .map(|(index, (depth, _))| (index, *depth))
+                                .collect();
+                            shapes.truncate(base_len);
+                            depths.truncate(base_len);
+                            for (index, _) in &changed_depths {
+                                depths[*index] = base_depths[*index];
+                            }
+
+                            let mut params = changes(params, &worker_data.params);
+                            params.retain(|(name, _)| index_name.as_ref() != Some(name));
+                            iterations.push(Iteration {
+                                index,
+                                new_shapes,
+                                new_depths,
+                                changed_depths,
+                                groups: data_groups
+                                    .iter()
+                                    .filter(|(name, group)| base_groups.get(*name) != Some(*group))
+                                    .map(|(name, group)| (name.clone(), group.clone()))
+                                    .collect(),
+                                attributes: changes(
+                                    base_attributes,
+                                    &worker_data.attributes.lock().unwrap(),
+                                ),
+                                params,
+                                collected,
+                                evaluations: worker_data.evaluations.load(Ordering::Relaxed)
+                                    - evaluations,
+                                events: std::mem::take(&mut *events.lock().unwrap()),
+                            });
+                        }
+
+                        Ok(iterations)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("A LoopOver worker panicked.".to_string()))
+                })
+                .collect::<Result<Vec<Vec<Iteration>>, String>>()
+        })?;
+
+        let mut iterations = results.into_iter().flatten().collect::<Vec<Iteration>>();
+        iterations.sort_by_key(|iteration| iteration.index);
+
+        let mut shapes = data.shapes.lock().unwrap();
+        let mut depths = data.depths.lock().unwrap();
+        let mut merged = HashMap::new();
+        let mut attributes = HashMap::new();
+        let mut params = HashMap::new();
+        let mut collected = Vec::new();
+        let mut evaluations = 0;
+        let mut events = Vec::new();
+
+        // Later iterations overwrite what earlier ones changed, as if run in order
+        for mut iteration in iterations {
+            let offset = shapes.len();
+            let remap = |group: Vec<Vec<usize>>| -> Vec<Vec<usize>> {
+                group
+                    .into_iter()
+                    .map(|indexes| {
+                        indexes
+                            .into_iter()
+                            .map(|index| {
+                                if index >= base_len {
+                                    index - base_len + offset
+                                } else {
+                                    index
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect()
+            };
+
+            for (name, group) in iteration.groups {
+                merged.insert(name, remap(group));
+            }
+            collected.extend(remap(iteration.collected));
+            for (index, depth) in iteration.changed_depths {
+                depths[index] = depth;
+            }
+            attributes.extend(iteration.attributes);
+            params.extend(iteration.params);
+            evaluations += iteration.evaluations;
+            events.append(&mut iteration.events);
+
+            shapes.append(&mut iteration.new_shapes);
+            depths.append(&mut iteration.new_depths);
+        }
+
+        drop(shapes);
+        drop(depths);
+        for (name, group) in merged {
+            data.set_group(&name, group);
+        }
+        {
+            let mut data_attributes = data.attributes.lock().unwrap();
+            let data_attributes = Arc::make_mut(&mut data_attributes);
+            for (name, group_attributes) in attributes {
+                match group_attributes {
+                    Some(group_attributes) => data_attributes.insert(name, group_attributes),
+                    None => data_attributes.remove(&name),
+                };
+            }
+        }
+        if let Some(collect) = &self.collect {
+            data.set_group(&collect.set_group, collected);
+        }
+        for (name, value) in params {
+            match value {
+                Some(value) => data.set_param(&name, value)?,
+                None => data.remove_param(&name)?,
+            }
+        }
+
+        data.evaluations.fetch_add(evaluations, Ordering::Relaxed);
+        if let Some(observer) = &mut data.observer {
+            for event in events {
+                match event {
+                    Event::Start(event) => observer.query_start(&event),
+                    Event::End(event) => observer.query_end(&event),
+                }
             }
         }
 
@@ -40,6 +432,11 @@ impl<T: Query> Query for LoopOver<T> {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, atomic::Ordering},
+    };
+
     use geo::polygon;
 
     use crate::*;
@@ -64,6 +461,9 @@ mod tests {
             get_group: "main".into(),
             iterator_name: "iter".into(),
             instructions,
+            index_name: None,
+            collect: None,
+            parallel: false,
         };
 
         if let Err(err) = loopover.query(&mut data) {
@@ -83,4 +483,257 @@ mod tests {
         let output = output.unwrap();
         assert_eq!(output, &vec![vec![0]]);
     }
+
+    #[test]
+    fn collect() {
+        let mut data = Data::from(vec![
+            polygon! {(0.0, 0.0).into()},
+            polygon! {(1.0, 0.0).into()},
+            polygon! {(2.0, 0.0).into()},
+        ]);
+
+        let mut loopover: LoopOver<Instruction> = LoopOver {
+            get_group: "main".into(),
+            iterator_name: "iter".into(),
+            instructions: vec![Instruction::Filter(Filter {
+                set_group: "output".into(),
+                get_group: "iter".into(),
+                code: "index != 1".into(),
+            })],
+            index_name: Some("index".into()),
+            collect: Some(Collect {
+                set_group: "all_outputs".into(),
+                get_group: "output".into(),
+            }),
+            parallel: false,
+        };
+
+        if let Err(err) = loopover.query(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        // The index doesn't outlive the loop, and a param it shadowed comes back
+        assert!(!data.params.contains_key("index"));
+        data.set_param("index", 7.into()).unwrap();
+        if let Err(err) = loopover.query(&mut data) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        assert_eq!(data.params["index"], 7);
+
+        let groups = data.groups.lock().unwrap();
+        let main = &groups["main"];
+        assert_eq!(
+            groups["all_outputs"],
+            vec![main[0].clone(), main[2].clone()]
+        );
+    }
+
+    #[test]
+    fn parallel() {
+        let polygons = (0..8)
+            .map(|x| polygon! {(x as f64, 0.0).into()})
+            .collect::<Vec<geo::Polygon>>();
+
+        let mut loopover: LoopOver<Instruction> = LoopOver {
+            get_group: "main".into(),
+            iterator_name: "iter".into(),
+            instructions: vec![Instruction::Transformation(Transformation {
+                set_group: "moved".into(),
+                get_group: "iter".into(),
+                transformation: [
+                    "1.0".into(),
+                    "0.0".into(),
+                    "0.0".into(),
+                    "0.0".into(),
+                    "1.0".into(),
+                    "index".into(),
+                ],
+            })],
+            index_name: Some("index".into()),
+            collect: Some(Collect {
+                set_group: "all_moved".into(),
+                get_group: "moved".into(),
+            }),
+            parallel: false,
+        };
+
+        let mut sequential = Data::from(polygons.clone());
+        if let Err(err) = loopover.query(&mut sequential) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        loopover.parallel = true;
+        let mut parallel = Data::from(polygons);
+        if let Err(err) = loopover.query(&mut parallel) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        assert_eq!(
+            *sequential.shapes.lock().unwrap(),
+            *parallel.shapes.lock().unwrap()
+        );
+        assert_eq!(
+            *sequential.groups.lock().unwrap(),
+            *parallel.groups.lock().unwrap()
+        );
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<(String, usize, usize)>>>,
+    }
+
+    impl Observer for Recorder {
+        fn query_end(&mut self, event: &QueryEnd) {
+            self.events
+                .lock()
+                .unwrap()
+                .push((event.name.clone(), event.depth, event.evaluations));
+        }
+    }
+
+    #[test]
+    fn parallel_state() {
+        let square = |min: f64, max: f64| {
+            polygon![
+                (x: min, y: min),
+                (x: max, y: min),
+                (x: max, y: max),
+                (x: min, y: max),
+            ]
+        };
+        let run = |parallel: bool| -> (Data, Vec<(String, usize, usize)>) {
+            let mut data = Data::from_parts(
+                vec![
+                    square(0.0, 10.0),
+                    square(1.0, 2.0),
+                    square(20.0, 30.0),
+                    square(21.0, 22.0),
+                ],
+                vec![0, 1, 0, 1],
+                HashMap::from([("inner".to_string(), vec![vec![1], vec![3]])]),
+            );
+            let recorder = Recorder::default();
+            let events = recorder.events.clone();
+            data.set_observer(Box::new(recorder));
+
+            let queries = vec![Instruction::LoopOver(LoopOver {
+                get_group: "inner".into(),
+                iterator_name: "iter".into(),
+                instructions: vec![
+                    Instruction::RecomputeDepths(RecomputeDepths {
+                        get_group: "iter".into(),
+                    }),
+                    Instruction::Map(Map {
+                        set_attribute: "area".into(),
+                        get_group: "iter".into(),
+                        code: "area('iter', i)".into(),
+                    }),
+                    Instruction::Let(Let {
+                        name: "last".into(),
+                        code: "index".into(),
+                    }),
+                ],
+                index_name: Some("index".into()),
+                collect: None,
+                parallel,
+            })];
+            if let Err(err) = data.query(queries) {
+                println!("Error: {}", err);
+                assert!(false);
+            }
+
+            let events = events.lock().unwrap().clone();
+            (data, events)
+        };
+
+        let (sequential, sequential_events) = run(false);
+        let (parallel, parallel_events) = run(true);
+
+        assert_eq!(*parallel.depths.lock().unwrap(), vec![0, 0, 0, 0]);
+        assert_eq!(
+            *sequential.depths.lock().unwrap(),
+            *parallel.depths.lock().unwrap()
+        );
+        assert_eq!(
+            *sequential.attributes.lock().unwrap(),
+            *parallel.attributes.lock().unwrap()
+        );
+        assert_eq!(parallel.params["last"], 1);
+        assert_eq!(sequential.params, parallel.params);
+        assert_eq!(
+            sequential.evaluations.load(Ordering::Relaxed),
+            parallel.evaluations.load(Ordering::Relaxed)
+        );
+        assert_eq!(sequential_events, parallel_events);
+    }
+
+    #[test]
+    fn parallel_dependency() {
+        let polygons = (0..6)
+            .map(|x| polygon! {(x as f64, 0.0).into()})
+            .collect::<Vec<geo::Polygon>>();
+        let pipeline = |code: &str, parallel: bool| {
+            vec![Instruction::LoopOver(LoopOver {
+                get_group: "main".into(),
+                iterator_name: "iter".into(),
+                instructions: vec![
+                    Instruction::Filter(Filter {
+                        set_group: "kept".into(),
+                        get_group: "iter".into(),
+                        code: code.into(),
+                    }),
+                    Instruction::Map(Map {
+                        set_attribute: "index".into(),
+                        get_group: "kept".into(),
+                        code: "index".into(),
+                    }),
+                    Instruction::Map(Map {
+                        set_attribute: "twice".into(),
+                        get_group: "kept".into(),
+                        code: "attr('kept', i, 'index') * 2".into(),
+                    }),
+                ],
+                index_name: Some("index".into()),
+                collect: Some(Collect {
+                    set_group: "all_kept".into(),
+                    get_group: "kept".into(),
+                }),
+                parallel,
+            })]
+        };
+
+        let mut sequential = Data::from(polygons.clone());
+        let mut parallel = Data::from(polygons.clone());
+        for (data, in_parallel) in [(&mut sequential, false), (&mut parallel, true)] {
+            if let Err(err) = data.query(pipeline("index % 2 == 0", in_parallel)) {
+                println!("Error: {}", err);
+                assert!(false);
+            }
+        }
+        assert_eq!(
+            *sequential.groups.lock().unwrap(),
+            *parallel.groups.lock().unwrap()
+        );
+        assert_eq!(
+            *sequential.attributes.lock().unwrap(),
+            *parallel.attributes.lock().unwrap()
+        );
+
+        // Reading 'kept' before the Filter sets it gets what the iteration before kept
+        let code = "len('kept') == 0 || index % 2 == 0";
+        let mut sequential = Data::from(polygons.clone());
+        assert!(sequential.query(pipeline(code, false)).is_ok());
+        let mut parallel = Data::from(polygons);
+        assert_eq!(
+            parallel.query(pipeline(code, true)),
+            Err(
+                "LoopOver can't run in parallel because iterations read 'kept' before setting it."
+                    .to_string()
+            )
+        );
+    }
 }
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use boa_engine::JsValue;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Evaluates `code` once per sub-group of `get_group` and stores the result as the
+/// `set_attribute` attribute of that sub-group. Later code reads it back with
+/// `attr('get_group', i, 'set_attribute')`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Map {
+    pub set_attribute: String,
+    pub get_group: String,
+    pub code: String,
+}
+
+impl Query for Map {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let len = {
+            let groups = data.groups.lock().unwrap();
+            let Some(shapes_indexes) = groups.get(&self.get_group) else {
+                return Err(format!("Could not find '{}' in groups.", self.get_group));
+            };
+            shapes_indexes.len()
+        };
+
+        let code = data.compile(&self.code, &["i"])?;
+
+        let mut values = Vec::with_capacity(len);
+        for i in 0..len {
+            let value = code
+                .call(&[JsValue::new(i)], &mut data.context)
+                .and_then(|value| value.to_json(&mut data.context))
+                .map_err(|err| format!("Map '{}' failed on {}: {}", self.set_attribute, i, err))?;
+            values.push(value);
+        }
+
+        let mut attributes = data.attributes.lock().unwrap();
+        Arc::make_mut(&mut attributes)
+            .entry(self.get_group.clone())
+            .or_default()
+            .insert(self.set_attribute.clone(), values);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![polygon![
+            (x: 0.0, y: 0.0),
+            (x: 2.0, y: 0.0),
+            (x: 2.0, y: 1.0),
+            (x: 0.0, y: 1.0),
+        ]]);
+
+        let queries: Vec<Box<dyn Query>> = vec![
+            Box::from(Map {
+                set_attribute: "frame".into(),
+                get_group: "main".into(),
+                code: "frame('main', i)".into(),
+            }),
+            Box::from(Filter {
+                set_group: "output".into(),
+                get_group: "main".into(),
+                code: "attr('main', i, 'frame').width == 2".into(),
+            }),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        {
+            let attributes = data.attributes.lock().unwrap();
+            assert_eq!(attributes["main"]["frame"][0]["height"], 1.0);
+
+            let groups = data.groups.lock().unwrap();
+            assert_eq!(groups["output"], vec![vec![0]]);
+        }
+
+        // Setting a group again drops the attributes of its old sub-groups
+        let queries: Vec<Box<dyn Query>> = vec![
+            Box::from(Filter {
+                set_group: "main".into(),
+                get_group: "main".into(),
+                code: "false".into(),
+            }),
+            Box::from(Filter {
+                set_group: "output".into(),
+                get_group: "output".into(),
+                code: "attr('main', 0, 'frame') === undefined".into(),
+            }),
+        ];
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        assert!(!data.attributes.lock().unwrap().contains_key("main"));
+        assert_eq!(data.groups.lock().unwrap()["output"], vec![vec![0]]);
+    }
+}
@@ -15,3 +15,27 @@ pub use kerning::*;
 
 pub mod sort;
 pub use sort::*;
+
+pub mod depths;
+pub use depths::*;
+
+pub mod group_ops;
+pub use group_ops::*;
+
+pub mod map;
+pub use map::*;
+
+pub mod let_param;
+pub use let_param::*;
+
+pub mod control_flow;
+pub use control_flow::*;
+
+pub mod call;
+pub use call::*;
+
+pub mod checkpoint;
+pub use checkpoint::*;
+
+pub mod text;
+pub use text::*;
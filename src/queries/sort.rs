@@ -1,13 +1,78 @@
-use boa_engine::{JsValue, Source, js_string, property::Attribute};
+use std::cmp::Ordering;
+
+use boa_engine::{Context, JsString, JsValue};
 use serde::{Deserialize, Serialize};
 
 use crate::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Orders the sub-groups of `get_group`.
+///
+/// Sub-groups are compared by each of `keys` in turn, then by `compare`, and finally by their
+/// position in `get_group`, so the result is always deterministic.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Sort {
     pub set_group: String,
     pub get_group: String,
+    /// Compares sub-groups `l` and `r`. Either a boolean that is true when `l` comes first,
+    /// or a number that is negative, zero or positive like a JS `Array.sort` comparator.
+    #[serde(default)]
     pub compare: String,
+    #[serde(default)]
+    pub keys: Vec<SortKey>,
+}
+
+/// A numeric key of sub-group `i` in the group named `g`, evaluated once per sub-group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortKey {
+    pub key: String,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl Sort {
+    /// Sorts `get_group` into `set_group` with the `compare` expression and no keys.
+    pub fn new(set_group: &str, get_group: &str, compare: &str) -> Self {
+        Self {
+            set_group: set_group.to_string(),
+            get_group: get_group.to_string(),
+            compare: compare.to_string(),
+            keys: Vec::new(),
+        }
+    }
+
+    /// Adds a key, compared after the keys before it and before `compare`.
+    pub fn key(mut self, key: &str, descending: bool) -> Self {
+        self.keys.push(SortKey {
+            key: key.to_string(),
+            descending,
+        });
+        self
+    }
+}
+
+/// Turns the result of the `compare` expression into an ordering of `l` and `r`.
+fn compare(code: &Expression, l: usize, r: usize, context: &mut Context) -> Ordering {
+    match code.call(&[JsValue::new(l), JsValue::new(r)], context) {
+        // A boolean only says whether `l` comes first, so always ask the other way round too.
+        // Both true, as `<=` gives for equal sub-groups, leaves them to their positions
+        Ok(JsValue::Boolean(first)) => {
+            let second = matches!(
+                code.call(&[JsValue::new(r), JsValue::new(l)], context),
+                Ok(JsValue::Boolean(true))
+            );
+            match (first, second) {
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _ => Ordering::Equal,
+            }
+        }
+        Ok(value) => value
+            .to_number(context)
+            .ok()
+            .and_then(|value| value.partial_cmp(&0.0))
+            .unwrap_or(Ordering::Equal),
+        Err(_) => Ordering::Equal,
+    }
 }
 
 impl Query for Sort {
@@ -20,26 +85,53 @@ impl Query for Sort {
             shapes_indexes.clone()
         };
 
+        let mut keys = Vec::with_capacity(self.keys.len());
+        for sort_key in &self.keys {
+            let code = data.compile(&sort_key.key, &["g", "i"])?;
+            let group_name = JsValue::from(JsString::from(self.get_group.as_str()));
+
+            let mut values = Vec::with_capacity(shapes_indexes.len());
+            for i in 0..shapes_indexes.len() {
+                // Anything that isn't a number sorts after every number
+                let value = code
+                    .call(&[group_name.clone(), JsValue::new(i)], &mut data.context)
+                    .and_then(|value| value.to_number(&mut data.context))
+                    .unwrap_or(f64::NAN);
+                values.push(value);
+            }
+            keys.push((values, sort_key.descending));
+        }
+
+        let compare_code = if self.compare.trim().is_empty() {
+            None
+        } else {
+            Some(data.compile(&self.compare, &["l", "r"])?)
+        };
+
         let mut indexes: Vec<usize> = (0..shapes_indexes.len()).collect();
         indexes.sort_by(|l, r| {
-            data.context
-                .register_global_property(js_string!("l"), *l, Attribute::all())
-                .expect("property shouldn't exist");
-
-            data.context
-                .register_global_property(js_string!("r"), *r, Attribute::all())
-                .expect("property shouldn't exist");
-
-            match data.context.eval(Source::from_bytes(&self.compare)) {
-                Ok(JsValue::Boolean(value)) => {
-                    if value {
-                        std::cmp::Ordering::Less
-                    } else {
-                        std::cmp::Ordering::Greater
-                    }
+            for (values, descending) in &keys {
+                let (l_value, r_value) = (values[*l], values[*r]);
+                let ordering = match (l_value.is_nan(), r_value.is_nan()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) if *descending => r_value.partial_cmp(&l_value).unwrap(),
+                    (false, false) => l_value.partial_cmp(&r_value).unwrap(),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+
+            if let Some(compare_code) = &compare_code {
+                let ordering = compare(compare_code, *l, *r, &mut data.context);
+                if ordering != Ordering::Equal {
+                    return ordering;
                 }
-                _ => std::cmp::Ordering::Equal,
             }
+
+            l.cmp(r)
         });
 
         let mut new_group = Vec::new();
@@ -48,9 +140,99 @@ impl Query for Sort {
             new_group.push(shapes_indexes[index].clone());
         }
 
-        let mut groups = data.groups.lock().unwrap();
-        groups.insert(self.set_group.clone(), new_group);
+        data.set_group(&self.set_group, new_group);
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{BoundingRect, polygon};
+
+    use crate::*;
+
+    fn square(x: f64, size: f64) -> geo::Polygon {
+        polygon![
+            (x: x, y: 0.0),
+            (x: x + size, y: 0.0),
+            (x: x + size, y: size),
+            (x: x, y: size),
+        ]
+    }
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![square(0.0, 1.0), square(2.0, 2.0), square(4.0, 1.0)]);
+        let squares = {
+            let shapes = data.shapes.lock().unwrap();
+            let index_of = |x: f64| {
+                shapes
+                    .iter()
+                    .position(|shape| shape.bounding_rect().unwrap().min().x == x)
+                    .unwrap()
+            };
+            vec![
+                vec![index_of(0.0)],
+                vec![index_of(2.0)],
+                vec![index_of(4.0)],
+            ]
+        };
+        data.set_group("squares", squares.clone());
+
+        let queries: Vec<Box<dyn Query>> = vec![
+            Box::from(Sort {
+                set_group: "by_size".into(),
+                get_group: "squares".into(),
+                compare: String::new(),
+                keys: vec![
+                    SortKey {
+                        key: "frame(g, i).width".into(),
+                        descending: true,
+                    },
+                    SortKey {
+                        key: "frame(g, i).min_x".into(),
+                        descending: false,
+                    },
+                ],
+            }),
+            Box::from(Sort::new(
+                "ties",
+                "squares",
+                "frame('squares', l).width < frame('squares', r).width",
+            )),
+            Box::from(Sort::new(
+                "ties_or_equal",
+                "squares",
+                "frame('squares', l).width <= frame('squares', r).width",
+            )),
+            Box::from(
+                Sort::new("by_size_built", "squares", "")
+                    .key("frame(g, i).width", true)
+                    .key("frame(g, i).min_x", false),
+            ),
+        ];
+
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let groups = data.groups.lock().unwrap();
+        assert_eq!(
+            groups["by_size"],
+            vec![squares[1].clone(), squares[0].clone(), squares[2].clone()]
+        );
+        assert_eq!(groups["by_size_built"], groups["by_size"]);
+        // Equal widths keep their original order
+        assert_eq!(
+            groups["ties"],
+            vec![squares[0].clone(), squares[2].clone(), squares[1].clone()]
+        );
+        assert_eq!(groups["ties_or_equal"], groups["ties"]);
+    }
 }
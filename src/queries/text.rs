@@ -0,0 +1,118 @@
+use geo::coord;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Lays out `text` with the TrueType or OpenType font at `font` into `set_group`, one
+/// sub-group per glyph with its counters, see `text_to_polygons`.
+///
+/// `size`, `x` and `y` are code, the em size and where the first baseline starts. An empty
+/// `tolerance` flattens curves to a thousandth of the size.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Text {
+    pub set_group: String,
+    pub font: String,
+    pub text: String,
+    pub size: String,
+    pub x: String,
+    pub y: String,
+    #[serde(default)]
+    pub kerning: bool,
+    #[serde(default)]
+    pub tolerance: String,
+}
+
+fn number(code: &str, data: &mut Data) -> Result<f64, String> {
+    let expression = data.compile(code, &[])?;
+    expression
+        .call(&[], &mut data.context)
+        .and_then(|value| value.to_number(&mut data.context))
+        .map_err(|err| format!("Could not evaluate '{}': {}", code, err))
+}
+
+impl Query for Text {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        let size = number(&self.size, data)?;
+        let origin = coord! {x: number(&self.x, data)?, y: number(&self.y, data)?};
+        let tolerance = if self.tolerance.trim().is_empty() {
+            size / 1000.0
+        } else {
+            number(&self.tolerance, data)?
+        };
+
+        let font = std::fs::read(&self.font)
+            .map_err(|err| format!("Could not read '{}': {}", self.font, err))?;
+        let glyphs = text_to_polygons(&font, &self.text, size, origin, self.kerning, tolerance)?;
+
+        let mut new_group = Vec::new();
+        {
+            let mut shapes = data.shapes.lock().unwrap();
+            let mut depths = data.depths.lock().unwrap();
+            for glyph in glyphs {
+                let mut g_index = Vec::new();
+                for polygon in glyph {
+                    g_index.push(shapes.len());
+                    shapes.push(polygon);
+                    depths.push(0);
+                }
+                new_group.push(g_index);
+            }
+        }
+
+        // Counters nest inside their glyph, and glyphs don't nest inside shapes already there
+        let indexes: Vec<usize> = new_group.iter().flatten().copied().collect();
+        data.recompute_depths(&indexes);
+
+        data.set_group(&self.set_group, new_group);
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let path = std::env::temp_dir().join(format!("gel-text-test-{}.ttf", std::process::id()));
+        std::fs::write(&path, crate::font::tests::font()).unwrap();
+
+        let mut data = Data::from(vec![polygon![
+            (x: -1.0, y: -1.0),
+            (x: 10.0, y: -1.0),
+            (x: 10.0, y: 5.0),
+            (x: -1.0, y: 5.0),
+        ]]);
+        let mut text = Text {
+            set_group: "letters".into(),
+            font: path.to_string_lossy().into(),
+            text: "OI".into(),
+            size: "2".into(),
+            x: "0".into(),
+            y: "0".into(),
+            kerning: true,
+            tolerance: String::new(),
+        };
+        let result = data.run(&mut text);
+        std::fs::remove_file(&path).unwrap();
+        if let Err(err) = result {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let letters = data.groups.lock().unwrap()["letters"].clone();
+        assert_eq!(letters, vec![vec![1, 2], vec![3]]);
+        // The counter of the O is inside it, and the sign around them doesn't count
+        assert_eq!(data.depths.lock().unwrap().clone(), vec![0, 0, 1, 0]);
+
+        text.font = "missing.ttf".into();
+        assert!(data.run(&mut text).is_err());
+    }
+}
@@ -1,4 +1,3 @@
-use boa_engine::Source;
 use geo::{AffineOps, AffineTransform};
 use serde::{Deserialize, Serialize};
 
@@ -23,10 +22,8 @@ impl Query for Transformation {
 
         let mut t_matrix = [0.; 6];
         for i in 0..6 {
-            if let Ok(value) = data
-                .context
-                .eval(Source::from_bytes(&self.transformation[i]))
-            {
+            let code = data.compile(&self.transformation[i], &[])?;
+            if let Ok(value) = code.call(&[], &mut data.context) {
                 if let Ok(value) = value.to_f32(&mut data.context) {
                     t_matrix[i] = value as f64;
                 }
@@ -45,23 +42,33 @@ impl Query for Transformation {
         let mut new_group = Vec::new();
 
         let mut shapes = data.shapes.lock().unwrap();
+        let mut depths = data.depths.lock().unwrap();
 
         let mut new_shapes = Vec::new();
+        let mut new_depths = Vec::new();
 
         for shapes_index in shapes_indexes {
             let mut ng = Vec::new();
             for index in shapes_index {
                 ng.push(new_shapes.len() + shapes.len());
                 new_shapes.push(shapes[index].affine_transform(&transformation));
+                // Transformed shapes keep the depth of the shape they came from
+                new_depths.push(depths[index]);
             }
             new_group.push(ng);
         }
 
-        let mut groups = data.groups.lock().unwrap();
-        groups.insert(self.set_group.clone(), new_group);
-
         shapes.append(&mut new_shapes);
+        depths.append(&mut new_depths);
+        drop(shapes);
+        drop(depths);
+
+        data.set_group(&self.set_group, new_group);
 
         Ok(())
     }
+
+    fn describe(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
 }
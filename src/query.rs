@@ -1,13 +1,91 @@
-use crate::Data;
+use crate::{Data, Instruction};
 
 pub trait Query {
     fn query(&mut self, data: &mut Data) -> Result<(), String>;
+
+    /// A copy of this query that can be sent to another thread, used by a parallel
+    /// `LoopOver`. Queries that can't be copied return `None`.
+    fn fork(&self) -> Option<Box<dyn Query + Send>> {
+        None
+    }
+
+    /// The `Instruction` this query is, so a parallel `LoopOver` can check what its
+    /// instructions read and write. Other queries return `None`.
+    fn instruction(&self) -> Option<&Instruction> {
+        None
+    }
+
+    /// The name reported to an `Observer`, the type name by default.
+    fn name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    /// The parameters reported to an `Observer`, as JSON.
+    fn describe(&self) -> String {
+        String::new()
+    }
+}
+
+/// The JSON of `instructions` the way a list of `Instruction` serializes, built from their
+/// names and `describe`, for queries that hold other queries.
+pub(crate) fn describe_all<T: Query>(instructions: &[T]) -> serde_json::Value {
+    instructions
+        .iter()
+        .map(|instruction| {
+            let parameters =
+                serde_json::from_str(&instruction.describe()).unwrap_or(serde_json::Value::Null);
+            serde_json::Value::Object(serde_json::Map::from_iter([(
+                instruction.name(),
+                parameters,
+            )]))
+        })
+        .collect()
 }
 
 impl Query for Box<dyn Query> {
     fn query(&mut self, data: &mut Data) -> Result<(), String> {
         (**self).query(data)
     }
+
+    fn fork(&self) -> Option<Box<dyn Query + Send>> {
+        (**self).fork()
+    }
+
+    fn instruction(&self) -> Option<&Instruction> {
+        (**self).instruction()
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+impl Query for Box<dyn Query + Send> {
+    fn query(&mut self, data: &mut Data) -> Result<(), String> {
+        (**self).query(data)
+    }
+
+    fn fork(&self) -> Option<Box<dyn Query + Send>> {
+        (**self).fork()
+    }
+
+    fn instruction(&self) -> Option<&Instruction> {
+        (**self).instruction()
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
 }
 
 impl<T> Query for Box<T>
@@ -17,4 +95,20 @@ where
     fn query(&mut self, data: &mut Data) -> Result<(), String> {
         (**self).query(data)
     }
+
+    fn fork(&self) -> Option<Box<dyn Query + Send>> {
+        (**self).fork()
+    }
+
+    fn instruction(&self) -> Option<&Instruction> {
+        (**self).instruction()
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
 }
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Coord, MultiPolygon, coord};
+
+use crate::{Data, Unit, flatten::arc};
+
+/// How closely the swivel arcs of blade offset compensation are followed, in mm.
+const ARC_TOLERANCE: f64 = 0.01;
+
+/// How `groups_to_hpgl` plots the groups.
+#[derive(Debug, Clone)]
+pub struct HpglOptions {
+    /// The unit of the shapes.
+    pub unit: Unit,
+    /// Plotter units per mm, 40 on most cutters.
+    pub units_per_mm: f64,
+    /// The pen or tool selected for each group, pen 1 otherwise.
+    pub pens: HashMap<String, u32>,
+    /// How far to keep cutting past the start of a closed contour, in mm.
+    pub overcut: f64,
+    /// How far the tip of a drag knife trails behind its axis, in mm. Zero turns off the
+    /// compensation.
+    pub blade_offset: f64,
+}
+
+impl Default for HpglOptions {
+    fn default() -> Self {
+        Self {
+            unit: Unit::default(),
+            units_per_mm: 40.0,
+            pens: HashMap::new(),
+            overcut: 0.0,
+            blade_offset: 0.0,
+        }
+    }
+}
+
+fn direction(from: Coord, to: Coord) -> Coord {
+    let delta = to - from;
+    delta / delta.x.hypot(delta.y)
+}
+
+/// The closed `ring` followed on for another `overcut` past its start.
+fn overcut(ring: &[Coord], overcut: f64) -> Vec<Coord> {
+    let mut path = ring.to_vec();
+    let mut left = overcut;
+    for pair in ring.windows(2) {
+        if left <= 0.0 {
+            break;
+        }
+        let length = (pair[1] - pair[0]).x.hypot((pair[1] - pair[0]).y);
+        if length >= left {
+            path.push(pair[0] + direction(pair[0], pair[1]) * left);
+            break;
+        }
+        path.push(pair[1]);
+        left -= length;
+    }
+    path
+}
+
+/// The path the axis of a drag knife takes so that its tip, trailing `offset` behind, cuts
+/// `path`. The axis leads the tip along each segment and swivels around every corner.
+fn blade_offset(path: &[Coord], offset: f64) -> Vec<Coord> {
+    let mut compensated = Vec::new();
+    for (index, pair) in path.windows(2).enumerate() {
+        let heading = direction(pair[0], pair[1]);
+        if index == 0 {
+            compensated.push(pair[0] + heading * offset);
+        }
+        compensated.push(pair[1] + heading * offset);
+
+        let Some(next) = path.get(index + 2) else {
+            continue;
+        };
+        let next = direction(pair[1], *next);
+        let turn = (heading.x * next.y - heading.y * next.x)
+            .atan2(heading.x * next.x + heading.y * next.y);
+        if turn.abs() > 1e-9 {
+            let start = heading.y.atan2(heading.x);
+            compensated.extend(
+                arc(pair[1], offset, start, turn, ARC_TOLERANCE)
+                    .into_iter()
+                    .skip(1),
+            );
+        }
+    }
+    compensated
+}
+
+/// Plots every shape of `groups` as HPGL, lifting the pen between contours.
+///
+/// Each group is cut with its own pen, counters before the outlines around them, whether
+/// they are shapes of their own or interiors of a shape, starting from the bottom left of
+/// the drawing. Contours are closed, cut past their start by the
+/// overcut and then compensated for the blade offset.
+pub fn groups_to_hpgl(data: &Data, groups: &[&str], options: &HpglOptions) -> String {
+    let shapes = data.shapes.lock().unwrap();
+    let depths = data.depths.lock().unwrap();
+    let data_groups = data.groups.lock().unwrap();
+
+    let drawn: Vec<(&str, &Vec<Vec<usize>>)> = groups
+        .iter()
+        .filter_map(|name| data_groups.get(*name).map(|group| (*name, group)))
+        .collect();
+    let origin = MultiPolygon::new(
+        drawn
+            .iter()
+            .flat_map(|(_, group)| group.iter().flatten())
+            .map(|index| shapes[*index].clone())
+            .collect(),
+    )
+    .bounding_rect()
+    .map_or(coord! {x: 0.0, y: 0.0}, |frame| frame.min());
+
+    let mm = options.unit.points() / 72.0 * 25.4;
+    let plot = |point: Coord| {
+        let point = point * options.units_per_mm;
+        format!("{},{}", point.x.round() as i64, point.y.round() as i64)
+    };
+
+    let mut hpgl = String::from("IN;\n");
+    for (name, group) in drawn {
+        hpgl += &format!("SP{};\n", options.pens.get(name).copied().unwrap_or(1));
+
+        let mut indexes: Vec<usize> = group.iter().flatten().copied().collect();
+        indexes.sort_by_key(|index| std::cmp::Reverse(depths[*index]));
+
+        for index in indexes {
+            let shape = &shapes[index];
+            for ring in shape.interiors().iter().chain([shape.exterior()]) {
+                let mut ring: Vec<Coord> =
+                    ring.0.iter().map(|point| (*point - origin) * mm).collect();
+                ring.dedup();
+                if ring.len() < 3 {
+                    continue;
+                }
+                if ring.first() != ring.last() {
+                    ring.push(ring[0]);
+                }
+
+                let mut path = if options.overcut > 0.0 {
+                    overcut(&ring, options.overcut)
+                } else {
+                    ring
+                };
+                if options.blade_offset > 0.0 {
+                    path = blade_offset(&path, options.blade_offset);
+                }
+
+                let points: Vec<String> = path.iter().skip(1).map(|point| plot(*point)).collect();
+                hpgl += &format!("PU{};\nPD{};\n", plot(path[0]), points.join(","));
+            }
+        }
+    }
+    hpgl += "PU;\nSP0;\n";
+
+    hpgl
+}
+
+/// Writes `groups` to `path` as HPGL, see `groups_to_hpgl`.
+pub fn save_hpgl(
+    data: &Data,
+    groups: &[&str],
+    options: &HpglOptions,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    std::fs::write(path, groups_to_hpgl(data, groups, options))
+        .map_err(|err| format!("Could not write '{}': {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let data = Data::from(vec![
+            polygon![
+                (x: 0.0, y: 0.0),
+                (x: 1.0, y: 0.0),
+                (x: 1.0, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            polygon![
+                (x: 0.25, y: 0.25),
+                (x: 0.5, y: 0.25),
+                (x: 0.5, y: 0.5),
+                (x: 0.25, y: 0.5),
+            ],
+        ]);
+        data.set_group("cut", vec![vec![0, 1]]);
+
+        let mut options = HpglOptions::default();
+        options.pens.insert("cut".into(), 2);
+        let hpgl = groups_to_hpgl(&data, &["cut"], &options);
+        assert!(hpgl.starts_with("IN;\nSP2;\n"));
+        assert!(hpgl.ends_with("PU;\nSP0;\n"));
+        // An inch is 1016 plotter units, and the counter is cut first
+        let square = hpgl
+            .find("PU0,0;\nPD1016,0,1016,1016,0,1016,0,0;\n")
+            .unwrap();
+        let counter = hpgl
+            .find("PU254,254;\nPD508,254,508,508,254,508,254,254;\n")
+            .unwrap();
+        assert!(counter < square);
+
+        options.overcut = 1.0;
+        let hpgl = groups_to_hpgl(&data, &["cut"], &options);
+        assert!(hpgl.contains("PU0,0;\nPD1016,0,1016,1016,0,1016,0,0,40,0;\n"));
+
+        options.overcut = 0.0;
+        options.blade_offset = 0.25;
+        let hpgl = groups_to_hpgl(&data, &["cut"], &options);
+        // The axis leads the blade by 10 plotter units and swivels around the corners
+        assert!(hpgl.contains("PU10,0;\nPD1026,0,"));
+        assert!(hpgl.contains(",1016,10,1016,1026,"));
+
+        // The interiors of a shape are cut before its exterior too
+        let data = Data::from(vec![polygon![
+            exterior: [
+                (x: 0.0, y: 0.0),
+                (x: 1.0, y: 0.0),
+                (x: 1.0, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            interiors: [[
+                (x: 0.25, y: 0.25),
+                (x: 0.5, y: 0.25),
+                (x: 0.5, y: 0.5),
+                (x: 0.25, y: 0.5),
+            ]],
+        ]]);
+        let hpgl = groups_to_hpgl(&data, &["main"], &HpglOptions::default());
+        let square = hpgl
+            .find("PU0,0;\nPD1016,0,1016,1016,0,1016,0,0;\n")
+            .unwrap();
+        let interior = hpgl
+            .find("PU254,254;\nPD508,254,508,508,254,508,254,254;\n")
+            .unwrap();
+        assert!(interior < square);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Coord, LineString, MultiPolygon, Polygon, Rect, coord};
+
+use crate::Data;
+
+const POINTS_PER_INCH: f64 = 72.0;
+const MARGIN: f64 = 36.0;
+/// Room left of and below the drawing for the dimensions, in points.
+const DIMENSION_SPACE: f64 = 36.0;
+const FONT_SIZE: f64 = 9.0;
+
+/// Colors given to groups without one in `PdfOptions::colors`, in the order of the groups.
+pub(crate) const PALETTE: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [214, 39, 40],
+    [31, 119, 180],
+    [44, 160, 44],
+    [255, 127, 14],
+    [148, 103, 189],
+];
+
+/// The length of one unit of the shapes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Unit {
+    #[default]
+    Inch,
+    Millimeter,
+}
+
+impl Unit {
+    /// How many PDF points, 1/72 of an inch, one unit is.
+    pub(crate) fn points(self) -> f64 {
+        match self {
+            Unit::Inch => POINTS_PER_INCH,
+            Unit::Millimeter => POINTS_PER_INCH / 25.4,
+        }
+    }
+
+    fn label(self, length: f64) -> String {
+        match self {
+            Unit::Inch => format!("{:.3} in", length),
+            Unit::Millimeter => format!("{:.1} mm", length),
+        }
+    }
+}
+
+/// How `groups_to_pdf` draws the groups.
+#[derive(Debug, Clone, Default)]
+pub struct PdfOptions {
+    pub unit: Unit,
+    /// RGB colors keyed by group name.
+    pub colors: HashMap<String, [u8; 3]>,
+    /// Fills every sub-group, leaving counters open, instead of only stroking the outlines.
+    pub fill: bool,
+    /// Adds the overall width and height of the drawing.
+    pub dimensions: bool,
+}
+
+fn ring_to_pdf(ring: &LineString, map: &impl Fn(Coord) -> Coord) -> String {
+    let mut path = String::new();
+    for (index, point) in ring.0.iter().enumerate() {
+        let point = map(*point);
+        let operator = if index == 0 { "m" } else { "l" };
+        path += &format!("{:.4} {:.4} {}\n", point.x, point.y, operator);
+    }
+    path + "h\n"
+}
+
+fn polygon_to_pdf(polygon: &Polygon, map: &impl Fn(Coord) -> Coord) -> String {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| ring_to_pdf(ring, map))
+        .collect()
+}
+
+fn line(from: Coord, to: Coord) -> String {
+    format!(
+        "{:.4} {:.4} m {:.4} {:.4} l S\n",
+        from.x, from.y, to.x, to.y
+    )
+}
+
+/// Text centered on `at`, turned a quarter counter clockwise when `vertical`.
+fn label(text: &str, at: Coord, vertical: bool) -> String {
+    // Helvetica digits are a little over half an em wide
+    let half = text.len() as f64 * FONT_SIZE * 0.28;
+    let matrix = if vertical {
+        format!("0 1 -1 0 {:.4} {:.4}", at.x, at.y - half)
+    } else {
+        format!("1 0 0 1 {:.4} {:.4}", at.x - half, at.y)
+    };
+    format!("BT /F1 {} Tf {} Tm ({}) Tj ET\n", FONT_SIZE, matrix, text)
+}
+
+/// Dimension lines for the width below the drawing and the height left of it.
+fn dimensions(frame: Rect, page: Rect, unit: Unit) -> String {
+    let (min, max) = (page.min(), page.max());
+    let below = min.y - 14.0;
+    let left = min.x - 14.0;
+
+    let mut content = String::from("q 0 0 0 RG 0 0 0 rg 0.5 w\n");
+    content += &line(coord! {x: min.x, y: below}, coord! {x: max.x, y: below});
+    content += &line(
+        coord! {x: min.x, y: below - 4.0},
+        coord! {x: min.x, y: min.y - 4.0},
+    );
+    content += &line(
+        coord! {x: max.x, y: below - 4.0},
+        coord! {x: max.x, y: min.y - 4.0},
+    );
+    content += &label(
+        &unit.label(frame.width()),
+        coord! {x: (min.x + max.x) / 2.0, y: below - 4.0 - FONT_SIZE},
+        false,
+    );
+
+    content += &line(coord! {x: left, y: min.y}, coord! {x: left, y: max.y});
+    content += &line(
+        coord! {x: left - 4.0, y: min.y},
+        coord! {x: min.x - 4.0, y: min.y},
+    );
+    content += &line(
+        coord! {x: left - 4.0, y: max.y},
+        coord! {x: min.x - 4.0, y: max.y},
+    );
+    content += &label(
+        &unit.label(frame.height()),
+        coord! {x: left - 4.0, y: (min.y + max.y) / 2.0},
+        true,
+    );
+
+    content + "Q\n"
+}
+
+/// A PDF with a single page of `width` by `height` points showing `content`.
+fn write_pdf(width: f64, height: f64, content: &str) -> String {
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.4} {:.4}] /Resources << /Font << /F1 5 0 R >> >> /Contents 4 0 R >>",
+            width, height
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            content.len(),
+            content
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf += &format!("{} 0 obj\n{}\nendobj\n", index + 1, object);
+    }
+
+    let xref = pdf.len();
+    pdf += &format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        pdf += &format!("{:010} 00000 n \n", offset);
+    }
+    pdf += &format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    pdf
+}
+
+/// Draws every sub-group of `groups` as vector paths in a one page PDF at true size.
+pub fn groups_to_pdf(data: &Data, groups: &[&str], options: &PdfOptions) -> String {
+    let shapes = data.shapes.lock().unwrap();
+    let data_groups = data.groups.lock().unwrap();
+
+    let drawn: Vec<(&str, &Vec<Vec<usize>>)> = groups
+        .iter()
+        .filter_map(|name| data_groups.get(*name).map(|group| (*name, group)))
+        .collect();
+    let Some(frame) = MultiPolygon::new(
+        drawn
+            .iter()
+            .flat_map(|(_, group)| group.iter().flatten())
+            .map(|index| shapes[*index].clone())
+            .collect(),
+    )
+    .bounding_rect() else {
+        return write_pdf(2.0 * MARGIN, 2.0 * MARGIN, "");
+    };
+
+    let scale = options.unit.points();
+    let offset = if options.dimensions {
+        MARGIN + DIMENSION_SPACE
+    } else {
+        MARGIN
+    };
+    let map = |point: Coord| {
+        coord! {
+            x: offset + (point.x - frame.min().x) * scale,
+            y: offset + (point.y - frame.min().y) * scale,
+        }
+    };
+    let page = Rect::new(map(frame.min()), map(frame.max()));
+
+    let mut content = String::from("1 J 1 j 0.5 w\n");
+    for (n, (name, group)) in drawn.iter().enumerate() {
+        let [r, g, b] = options
+            .colors
+            .get(*name)
+            .copied()
+            .unwrap_or(PALETTE[n % PALETTE.len()]);
+        let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+        content += &format!(
+            "{:.3} {:.3} {:.3} RG {:.3} {:.3} {:.3} rg\n",
+            r, g, b, r, g, b
+        );
+
+        // One path per sub-group so the even-odd rule leaves its counters open
+        for sub_group in group.iter() {
+            for index in sub_group {
+                content += &polygon_to_pdf(&shapes[*index], &map);
+            }
+            content += if options.fill { "B*\n" } else { "S\n" };
+        }
+    }
+    if options.dimensions {
+        content += &dimensions(frame, page, options.unit);
+    }
+
+    write_pdf(page.max().x + MARGIN, page.max().y + MARGIN, &content)
+}
+
+/// Writes `groups` to `path` as a PDF, see `groups_to_pdf`.
+pub fn save_pdf(
+    data: &Data,
+    groups: &[&str],
+    options: &PdfOptions,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    std::fs::write(path, groups_to_pdf(data, groups, options))
+        .map_err(|err| format!("Could not write '{}': {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let data = Data::from(vec![
+            polygon![
+                (x: 0.0, y: 0.0),
+                (x: 2.0, y: 0.0),
+                (x: 2.0, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            polygon![
+                (x: 0.5, y: 0.25),
+                (x: 1.0, y: 0.25),
+                (x: 1.0, y: 0.75),
+                (x: 0.5, y: 0.75),
+            ],
+        ]);
+
+        let pdf = groups_to_pdf(&data, &["main"], &PdfOptions::default());
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        // Two inches by one inch and half an inch of margin all around
+        assert!(pdf.contains("/MediaBox [0 0 216.0000 144.0000]"));
+        assert!(pdf.contains("36.0000 36.0000 m"));
+        assert_eq!(pdf.matches("\nS\n").count(), 2);
+        assert!(!pdf.contains("BT"));
+
+        // Every object starts where the cross reference table says it does
+        let xref = pdf.rfind("xref\n").unwrap();
+        let offsets: Vec<usize> = pdf[xref..]
+            .lines()
+            .skip(3)
+            .take(5)
+            .map(|line| line[..10].parse().unwrap())
+            .collect();
+        for (index, offset) in offsets.iter().enumerate() {
+            assert!(pdf[*offset..].starts_with(&format!("{} 0 obj", index + 1)));
+        }
+        assert!(pdf.contains(&format!("startxref\n{}\n", xref)));
+
+        let mut options = PdfOptions {
+            unit: Unit::Millimeter,
+            fill: true,
+            dimensions: true,
+            ..Default::default()
+        };
+        options.colors.insert("main".into(), [255, 0, 0]);
+        let pdf = groups_to_pdf(&data, &["main"], &options);
+        assert!(pdf.contains("1.000 0.000 0.000 rg"));
+        assert_eq!(pdf.matches("B*").count(), 2);
+        assert!(pdf.contains("(2.0 mm) Tj"));
+        assert!(pdf.contains("(1.0 mm) Tj"));
+
+        let path =
+            std::env::temp_dir().join(format!("gel-save-pdf-test-{}.pdf", std::process::id()));
+        if let Err(err) = save_pdf(&data, &["main"], &options, &path) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
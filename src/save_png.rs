@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Coord, LineString, MultiPolygon, Rect, coord};
+
+use crate::{Data, Unit, save_pdf::PALETTE};
+
+/// Rows of each pixel sampled for anti-aliasing. Coverage along a row is exact.
+const SAMPLES: usize = 5;
+/// Blank pixels around the drawing.
+const MARGIN: f64 = 2.0;
+const MAX_PIXELS: f64 = 1e8;
+
+/// Which parts of a path with crossing or nested rings are inside.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FillRule {
+    /// Inside where a ray crosses an odd number of rings, so counters stay open.
+    #[default]
+    EvenOdd,
+    /// Inside where the rings around a point don't cancel out by their direction.
+    NonZero,
+}
+
+impl FillRule {
+    fn inside(self, winding: i32) -> bool {
+        match self {
+            FillRule::EvenOdd => winding % 2 != 0,
+            FillRule::NonZero => winding != 0,
+        }
+    }
+}
+
+/// Whether shapes are filled or only their outlines drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Paint {
+    #[default]
+    Fill,
+    /// Outlines `width` pixels wide.
+    Stroke { width: f64 },
+}
+
+/// How `groups_to_png` renders the groups.
+#[derive(Debug, Clone)]
+pub struct RasterOptions {
+    pub dpi: f64,
+    pub unit: Unit,
+    pub paint: Paint,
+    pub fill_rule: FillRule,
+    /// RGB colors keyed by group name, the same defaults as `groups_to_pdf` otherwise.
+    pub colors: HashMap<String, [u8; 3]>,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        Self {
+            dpi: 96.0,
+            unit: Unit::default(),
+            paint: Paint::default(),
+            fill_rule: FillRule::default(),
+            colors: HashMap::new(),
+        }
+    }
+}
+
+/// Edges of rings in pixels, to be filled together as one path.
+#[derive(Debug, Default)]
+struct Path {
+    edges: Vec<(Coord, Coord)>,
+}
+
+impl Path {
+    fn ring(&mut self, points: &[Coord]) {
+        for (index, from) in points.iter().enumerate() {
+            let to = points[(index + 1) % points.len()];
+            if from.y != to.y {
+                self.edges.push((*from, to));
+            }
+        }
+    }
+
+    /// Adds `points` turning counter clockwise, so overlapping pieces of a stroke add up
+    /// under the nonzero rule instead of cancelling.
+    fn counter_clockwise(&mut self, mut points: Vec<Coord>) {
+        let area: f64 = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.x * b.y - b.x * a.y)
+            .sum();
+        if area < 0.0 {
+            points.reverse();
+        }
+        self.ring(&points);
+    }
+
+    /// The outline of `ring` as round joined segments `width` wide.
+    fn stroke(&mut self, ring: &[Coord], width: f64) {
+        let radius = width / 2.0;
+        for (index, from) in ring.iter().enumerate() {
+            let to = ring[(index + 1) % ring.len()];
+            let (dx, dy) = (to.x - from.x, to.y - from.y);
+            let length = dx.hypot(dy);
+            if length > 0.0 {
+                let normal = coord! {x: -dy / length * radius, y: dx / length * radius};
+                self.counter_clockwise(vec![
+                    *from + normal,
+                    to + normal,
+                    to - normal,
+                    *from - normal,
+                ]);
+            }
+            self.counter_clockwise(
+                (0..8)
+                    .map(|step| {
+                        let angle = step as f64 * std::f64::consts::TAU / 8.0;
+                        *from + coord! {x: angle.cos() * radius, y: angle.sin() * radius}
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    /// The pixels of a `width` by `height` image the path can touch, as the columns
+    /// `left..right` and the rows `top..bottom` in `[left, top, right, bottom]`.
+    fn bounds(&self, width: usize, height: usize) -> Option<[usize; 4]> {
+        let mut min = coord! {x: f64::MAX, y: f64::MAX};
+        let mut max = coord! {x: f64::MIN, y: f64::MIN};
+        for point in self.edges.iter().flat_map(|(a, b)| [a, b]) {
+            min = coord! {x: min.x.min(point.x), y: min.y.min(point.y)};
+            max = coord! {x: max.x.max(point.x), y: max.y.max(point.y)};
+        }
+
+        let (left, top) = (
+            min.x.floor().max(0.0) as usize,
+            min.y.floor().max(0.0) as usize,
+        );
+        let right = (max.x.ceil().max(0.0) as usize).min(width);
+        let bottom = (max.y.ceil().max(0.0) as usize).min(height);
+        if left >= right || top >= bottom {
+            return None;
+        }
+
+        Some([left, top, right, bottom])
+    }
+
+    /// How much of every pixel within `bounds` the path covers, from 0 to 1, row by row.
+    fn coverage(&self, bounds: [usize; 4], fill_rule: FillRule) -> Vec<f64> {
+        let [left, top, right, bottom] = bounds;
+        let width = right - left;
+        let mut coverage = vec![0.0; width * (bottom - top)];
+
+        // The edges that can cross each row
+        let mut rows: Vec<Vec<usize>> = vec![Vec::new(); bottom - top];
+        for (index, (a, b)) in self.edges.iter().enumerate() {
+            let from = (a.y.min(b.y).floor().max(0.0) as usize).max(top);
+            let to = (a.y.max(b.y).ceil().max(0.0) as usize).min(bottom);
+            for row in rows
+                .iter_mut()
+                .take(to.saturating_sub(top))
+                .skip(from - top)
+            {
+                row.push(index);
+            }
+        }
+
+        let mut crossings: Vec<(f64, i32)> = Vec::new();
+        for (row, edges) in rows.iter().enumerate() {
+            let line = &mut coverage[row * width..(row + 1) * width];
+            for sample in 0..SAMPLES {
+                let y = (top + row) as f64 + (sample as f64 + 0.5) / SAMPLES as f64;
+
+                crossings.clear();
+                for (a, b) in edges.iter().map(|index| self.edges[*index]) {
+                    if (a.y <= y) != (b.y <= y) {
+                        let x = a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y) - left as f64;
+                        crossings.push((x, if a.y < b.y { 1 } else { -1 }));
+                    }
+                }
+                crossings.sort_by(|l, r| l.0.total_cmp(&r.0));
+
+                let mut winding = 0;
+                for pair in crossings.windows(2) {
+                    winding += pair[0].1;
+                    if fill_rule.inside(winding) {
+                        span(line, pair[0].0, pair[1].0, 1.0 / SAMPLES as f64);
+                    }
+                }
+            }
+        }
+
+        coverage
+    }
+}
+
+/// Adds `weight` times how much of each pixel of `line` lies between `from` and `to`.
+fn span(line: &mut [f64], from: f64, to: f64, weight: f64) {
+    let from = from.max(0.0);
+    let to = to.min(line.len() as f64);
+    if to <= from {
+        return;
+    }
+    let (first, last) = (from.floor() as usize, (to.ceil() as usize).min(line.len()));
+    for (x, pixel) in line.iter_mut().enumerate().take(last).skip(first) {
+        let overlap = to.min(x as f64 + 1.0) - from.max(x as f64);
+        *pixel += overlap.max(0.0) * weight;
+    }
+}
+
+/// Renders every sub-group of `groups` into RGB pixels on white, returning the width, the
+/// height and the pixels row by row from the top.
+pub fn rasterize(
+    data: &Data,
+    groups: &[&str],
+    options: &RasterOptions,
+) -> Result<(usize, usize, Vec<u8>), String> {
+    let shapes = data.shapes.lock().unwrap();
+    let data_groups = data.groups.lock().unwrap();
+
+    let drawn: Vec<(&str, &Vec<Vec<usize>>)> = groups
+        .iter()
+        .filter_map(|name| data_groups.get(*name).map(|group| (*name, group)))
+        .collect();
+    let frame = MultiPolygon::new(
+        drawn
+            .iter()
+            .flat_map(|(_, group)| group.iter().flatten())
+            .map(|index| shapes[*index].clone())
+            .collect(),
+    )
+    .bounding_rect()
+    .unwrap_or(Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 0.0, y: 0.0}));
+
+    // Pixels per unit of the shapes, with y pointing down in the image
+    let scale = options.dpi * options.unit.points() / 72.0;
+    let pad = match options.paint {
+        Paint::Fill => MARGIN,
+        Paint::Stroke { width } => MARGIN + width / 2.0,
+    };
+    let (width, height) = (
+        (frame.width() * scale + 2.0 * pad).ceil(),
+        (frame.height() * scale + 2.0 * pad).ceil(),
+    );
+    if !(width * height).is_finite() || width * height > MAX_PIXELS {
+        return Err(format!(
+            "A {} by {} image is too big to render.",
+            width, height
+        ));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let map = |point: &Coord| {
+        coord! {
+            x: pad + (point.x - frame.min().x) * scale,
+            y: pad + (frame.max().y - point.y) * scale,
+        }
+    };
+    let ring = |ring: &LineString| -> Vec<Coord> {
+        let mut points: Vec<Coord> = ring.0.iter().map(map).collect();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        points
+    };
+
+    let mut pixels = vec![255u8; width * height * 3];
+    for (n, (name, group)) in drawn.iter().enumerate() {
+        let color = options
+            .colors
+            .get(*name)
+            .copied()
+            .unwrap_or(PALETTE[n % PALETTE.len()]);
+
+        for sub_group in group.iter() {
+            let mut path = Path::default();
+            let rings = sub_group.iter().flat_map(|index| {
+                std::iter::once(shapes[*index].exterior()).chain(shapes[*index].interiors())
+            });
+            let fill_rule = match options.paint {
+                Paint::Fill => {
+                    rings.for_each(|points| path.ring(&ring(points)));
+                    options.fill_rule
+                }
+                Paint::Stroke { width } => {
+                    rings.for_each(|points| path.stroke(&ring(points), width));
+                    FillRule::NonZero
+                }
+            };
+
+            // Only the pixels around the sub-group are covered and blended
+            let Some(bounds @ [left, top, right, _]) = path.bounds(width, height) else {
+                continue;
+            };
+            let coverage = path.coverage(bounds, fill_rule);
+            for (row, line) in coverage.chunks_exact(right - left).enumerate() {
+                let start = ((top + row) * width + left) * 3;
+                let end = start + (right - left) * 3;
+                for (pixel, coverage) in pixels[start..end].chunks_exact_mut(3).zip(line) {
+                    let coverage = coverage.min(1.0);
+                    if coverage <= 0.0 {
+                        continue;
+                    }
+                    for (channel, color) in pixel.iter_mut().zip(color) {
+                        let blended = *channel as f64 * (1.0 - coverage) + color as f64 * coverage;
+                        *channel = blended.round() as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Renders every sub-group of `groups` as a PNG, see `rasterize`.
+pub fn groups_to_png(
+    data: &Data,
+    groups: &[&str],
+    options: &RasterOptions,
+) -> Result<Vec<u8>, String> {
+    let (width, height, pixels) = rasterize(data, groups, options)?;
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|err| format!("Could not write PNG: {}", err))?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(|err| format!("Could not write PNG: {}", err))?;
+    writer
+        .finish()
+        .map_err(|err| format!("Could not write PNG: {}", err))?;
+
+    Ok(png)
+}
+
+/// Writes `groups` to `path` as a PNG, see `rasterize`.
+pub fn save_png(
+    data: &Data,
+    groups: &[&str],
+    options: &RasterOptions,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    std::fs::write(path, groups_to_png(data, groups, options)?)
+        .map_err(|err| format!("Could not write '{}': {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    fn pixel(image: &(usize, usize, Vec<u8>), x: usize, y: usize) -> [u8; 3] {
+        let at = (y * image.0 + x) * 3;
+        [image.2[at], image.2[at + 1], image.2[at + 2]]
+    }
+
+    #[test]
+    fn it_works() {
+        // A square with a square counter, as two shapes of one sub-group
+        let mut data = Data::from(vec![
+            polygon![
+                (x: 0.0, y: 0.0),
+                (x: 1.03125, y: 0.0),
+                (x: 1.03125, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            polygon![
+                (x: 0.25, y: 0.25),
+                (x: 0.75, y: 0.25),
+                (x: 0.75, y: 0.75),
+                (x: 0.25, y: 0.75),
+            ],
+        ]);
+        data.set_group("sign", vec![vec![0, 1]]);
+
+        let options = RasterOptions {
+            dpi: 16.0,
+            ..Default::default()
+        };
+        let image = match rasterize(&data, &["sign"], &options) {
+            Ok(image) => image,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        // 16.5 by 16 pixels of drawing and a margin of 2 all around
+        assert_eq!((image.0, image.1), (21, 20));
+        assert_eq!(pixel(&image, 0, 0), [255, 255, 255]);
+        assert_eq!(pixel(&image, 3, 3), [0, 0, 0]);
+        // The counter stays open with the even-odd rule
+        assert_eq!(pixel(&image, 10, 10), [255, 255, 255]);
+        // The right edge ends halfway through a pixel
+        let edge = pixel(&image, 18, 10)[0];
+        assert!(edge > 100 && edge < 155);
+
+        let nonzero = rasterize(
+            &data,
+            &["sign"],
+            &RasterOptions {
+                fill_rule: FillRule::NonZero,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(pixel(&nonzero, 10, 10), [0, 0, 0]);
+
+        let mut stroke = RasterOptions {
+            paint: Paint::Stroke { width: 2.0 },
+            ..options.clone()
+        };
+        stroke.colors.insert("sign".into(), [255, 0, 0]);
+        let outline = rasterize(&data, &["sign"], &stroke).unwrap();
+        // The outline is a pixel wider than the margin on every side
+        assert_eq!(pixel(&outline, 2, 11), [255, 0, 0]);
+        assert_eq!(pixel(&outline, 4, 11), [255, 255, 255]);
+
+        let png = match groups_to_png(&data, &["sign"], &options) {
+            Ok(png) => png,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+        let mut reader = ::png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (21, 20));
+        assert_eq!(decoded, image.2);
+
+        let huge = RasterOptions {
+            dpi: 1e6,
+            ..options.clone()
+        };
+        assert!(rasterize(&data, &["sign"], &huge).is_err());
+    }
+}
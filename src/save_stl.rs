@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+
+use geo::{Centroid, Contains, Coord, MapCoords, Orient, Polygon, orient::Direction};
+
+use crate::{Data, Shapes, Unit, triangulate::triangulate};
+
+/// How many rings of facets make up the side of a dome.
+const DOME_RINGS: usize = 8;
+
+type Triangle = [[f64; 3]; 3];
+
+/// How the shapes of a group are raised, with lengths in the unit of the shapes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extrusion {
+    /// Straight walls from `base` up to `base + height`, for plates, letters and cylindrical
+    /// dots.
+    Prism { base: f64, height: f64 },
+    /// A spherical cap `height` tall on top of `base` over each shape, for braille domes.
+    Dome { base: f64, height: f64 },
+}
+
+/// How `groups_to_stl` raises the groups.
+#[derive(Debug, Clone, Default)]
+pub struct StlOptions {
+    /// The unit of the shapes.
+    pub unit: Unit,
+    /// How thick the plate is, in the unit of the shapes. Groups without an extrusion stand
+    /// on top of it, see `StlOptions::with_plate`.
+    pub plate: f64,
+    /// The extrusion of each group, 1/32" up from the top of the plate otherwise.
+    pub extrusions: HashMap<String, Extrusion>,
+    /// Writes ASCII STL instead of binary.
+    pub ascii: bool,
+}
+
+impl StlOptions {
+    /// Options where `group` is a plate `thickness` thick, for the other groups to stand on
+    /// instead of being buried in it.
+    pub fn with_plate(group: &str, thickness: f64) -> Self {
+        Self {
+            plate: thickness,
+            extrusions: HashMap::from([(
+                group.to_string(),
+                Extrusion::Prism {
+                    base: 0.0,
+                    height: thickness,
+                },
+            )]),
+            ..Self::default()
+        }
+    }
+}
+
+fn point(point: Coord, z: f64) -> [f64; 3] {
+    [point.x, point.y, z]
+}
+
+/// Two triangles between the edge `a` `b` and the edge `c` `d` above it, facing out of a
+/// counter clockwise ring.
+fn wall(triangles: &mut Vec<Triangle>, a: [f64; 3], b: [f64; 3], c: [f64; 3], d: [f64; 3]) {
+    triangles.push([a, b, c]);
+    triangles.push([a, c, d]);
+}
+
+/// The floor at `bottom` under `polygon`, facing down.
+fn floor(triangles: &mut Vec<Triangle>, polygon: &Polygon, bottom: f64) {
+    for [a, b, c] in triangulate(polygon) {
+        triangles.push([point(a, bottom), point(c, bottom), point(b, bottom)]);
+    }
+}
+
+fn prism(triangles: &mut Vec<Triangle>, polygon: &Polygon, bottom: f64, top: f64) {
+    let polygon = polygon.orient(Direction::Default);
+    floor(triangles, &polygon, bottom);
+    for [a, b, c] in triangulate(&polygon) {
+        triangles.push([point(a, top), point(b, top), point(c, top)]);
+    }
+    for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+        for pair in ring.0.windows(2).filter(|pair| pair[0] != pair[1]) {
+            wall(
+                triangles,
+                point(pair[0], bottom),
+                point(pair[1], bottom),
+                point(pair[1], top),
+                point(pair[0], top),
+            );
+        }
+    }
+}
+
+/// A spherical cap over the exterior of `polygon`, its rings shrunk towards the centroid so
+/// round dots come out as true domes.
+fn dome(triangles: &mut Vec<Triangle>, polygon: &Polygon, bottom: f64, height: f64) {
+    let polygon = Polygon::new(polygon.exterior().clone(), Vec::new()).orient(Direction::Default);
+    let Some(center) = polygon.centroid().map(|center| center.0) else {
+        return;
+    };
+    let radius = polygon
+        .exterior()
+        .0
+        .iter()
+        .map(|point| (*point - center).x.hypot((*point - center).y))
+        .fold(0.0, f64::max);
+    if radius <= 0.0 || height <= 0.0 {
+        return;
+    }
+
+    // The sphere through the rim and the top, and the angle from its top down to the rim
+    let sphere = (radius * radius + height * height) / (2.0 * height);
+    let rim = (1.0 - height / sphere).acos();
+    let rings: Vec<(Vec<Coord>, f64)> = (0..DOME_RINGS)
+        .map(|ring| {
+            let angle = rim * (1.0 - ring as f64 / DOME_RINGS as f64);
+            let scale = sphere * angle.sin() / radius;
+            let points = polygon
+                .exterior()
+                .0
+                .iter()
+                .map(|point| center + (*point - center) * scale)
+                .collect();
+            (points, bottom + height - sphere * (1.0 - angle.cos()))
+        })
+        .collect();
+
+    floor(triangles, &polygon, bottom);
+    for pair in rings.windows(2) {
+        let ((lower, lower_z), (upper, upper_z)) = (&pair[0], &pair[1]);
+        for (lower, upper) in lower.windows(2).zip(upper.windows(2)) {
+            wall(
+                triangles,
+                point(lower[0], *lower_z),
+                point(lower[1], *lower_z),
+                point(upper[1], *upper_z),
+                point(upper[0], *upper_z),
+            );
+        }
+    }
+    let (last, z) = rings.last().unwrap();
+    let top = point(center, bottom + height);
+    for pair in last.windows(2) {
+        triangles.push([point(pair[0], *z), point(pair[1], *z), top]);
+    }
+}
+
+/// The shapes of a sub-group as solids, with the shapes one deeper inside them as holes.
+fn solids(shapes: &Shapes, depths: &[usize], sub_group: &[usize]) -> Vec<Polygon> {
+    let Some(top) = sub_group.iter().map(|index| depths[*index]).min() else {
+        return Vec::new();
+    };
+    let (outlines, counters): (Vec<usize>, Vec<usize>) = sub_group
+        .iter()
+        .partition(|index| (depths[**index] - top) % 2 == 0);
+
+    let mut solids: Vec<Polygon> = outlines
+        .iter()
+        .map(|index| shapes[*index].clone())
+        .collect();
+    for counter in counters {
+        let Some(inside) = shapes[counter].exterior().0.first() else {
+            continue;
+        };
+        if let Some(n) = outlines.iter().position(|outline| {
+            depths[*outline] + 1 == depths[counter] && shapes[*outline].contains(inside)
+        }) {
+            solids[n].interiors_push(shapes[counter].exterior().clone());
+        }
+    }
+    solids
+}
+
+/// Every triangle of `groups` raised as set in `options`, in mm.
+fn triangles(data: &Data, groups: &[&str], options: &StlOptions) -> Vec<Triangle> {
+    let shapes = data.shapes.lock().unwrap();
+    let depths = data.depths.lock().unwrap();
+    let data_groups = data.groups.lock().unwrap();
+
+    let mm = options.unit.points() / 72.0 * 25.4;
+    let raised = Extrusion::Prism {
+        base: options.plate,
+        height: Unit::Inch.points() / 32.0 / options.unit.points(),
+    };
+
+    let mut triangles = Vec::new();
+    for name in groups {
+        let Some(group) = data_groups.get(*name) else {
+            continue;
+        };
+        let extrusion = options.extrusions.get(*name).copied().unwrap_or(raised);
+        for sub_group in group {
+            for solid in solids(&shapes, &depths, sub_group) {
+                let solid = solid.map_coords(|point| point * mm);
+                match extrusion {
+                    Extrusion::Prism { base, height } => {
+                        prism(&mut triangles, &solid, base * mm, (base + height) * mm)
+                    }
+                    Extrusion::Dome { base, height } => {
+                        dome(&mut triangles, &solid, base * mm, height * mm)
+                    }
+                }
+            }
+        }
+    }
+    triangles
+}
+
+/// The unit normal of a triangle from its winding, zero when it has no area.
+fn normal([a, b, c]: &Triangle) -> [f64; 3] {
+    let (u, v) = (
+        [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+        [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+    );
+    let normal = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+    if length > 0.0 {
+        normal.map(|value| value / length)
+    } else {
+        [0.0; 3]
+    }
+}
+
+/// Extrudes every sub-group of `groups` into a mesh in mm, as set in `options`.
+///
+/// Shapes nested one deeper within a sub-group are cut out as counters, so the output of
+/// `Text` or `Kerning` prints as letters.
+pub fn groups_to_stl(data: &Data, groups: &[&str], options: &StlOptions) -> Vec<u8> {
+    let triangles = triangles(data, groups, options);
+
+    if options.ascii {
+        let mut stl = String::from("solid gel\n");
+        for triangle in &triangles {
+            let [x, y, z] = normal(triangle);
+            stl += &format!(
+                "facet normal {} {} {}\nouter loop\n",
+                x as f32, y as f32, z as f32
+            );
+            for [x, y, z] in triangle {
+                stl += &format!("vertex {} {} {}\n", *x as f32, *y as f32, *z as f32);
+            }
+            stl += "endloop\nendfacet\n";
+        }
+        stl += "endsolid gel\n";
+        return stl.into_bytes();
+    }
+
+    let mut stl = Vec::with_capacity(84 + 50 * triangles.len());
+    let mut header = [0u8; 80];
+    header[..3].copy_from_slice(b"gel");
+    stl.extend_from_slice(&header);
+    stl.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+    for triangle in &triangles {
+        for value in normal(triangle).iter().chain(triangle.iter().flatten()) {
+            stl.extend_from_slice(&(*value as f32).to_le_bytes());
+        }
+        stl.extend_from_slice(&0u16.to_le_bytes());
+    }
+    stl
+}
+
+/// Writes `groups` to `path` as STL, see `groups_to_stl`.
+pub fn save_stl(
+    data: &Data,
+    groups: &[&str],
+    options: &StlOptions,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    std::fs::write(path, groups_to_stl(data, groups, options))
+        .map_err(|err| format!("Could not write '{}': {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use geo::{Area, polygon};
+
+    use crate::*;
+
+    /// The triangles of a binary STL.
+    fn read(stl: &[u8]) -> Vec<[[f64; 3]; 3]> {
+        let count = u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize;
+        assert_eq!(stl.len(), 84 + 50 * count);
+        let value = |at: usize| f32::from_le_bytes(stl[at..at + 4].try_into().unwrap()) as f64;
+        (0..count)
+            .map(|n| {
+                let at = 84 + 50 * n + 12;
+                [0, 1, 2].map(|v| [0, 1, 2].map(|c| value(at + 12 * v + 4 * c)))
+            })
+            .collect()
+    }
+
+    /// The enclosed volume, positive when every facet faces out.
+    fn volume(triangles: &[[[f64; 3]; 3]]) -> f64 {
+        triangles
+            .iter()
+            .map(|[a, b, c]| {
+                (a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+                    + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                    / 6.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn it_works() {
+        let dot = geo::Polygon::new(
+            (0..16)
+                .map(|n| {
+                    let angle = n as f64 * std::f64::consts::TAU / 16.0;
+                    (1.5 + 0.03 * angle.cos(), 0.5 + 0.03 * angle.sin())
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            Vec::new(),
+        );
+        let data = Data::from_parts(
+            vec![
+                polygon![
+                    (x: 0.0, y: 0.0),
+                    (x: 2.0, y: 0.0),
+                    (x: 2.0, y: 1.0),
+                    (x: 0.0, y: 1.0),
+                ],
+                polygon![
+                    (x: 0.25, y: 0.25),
+                    (x: 0.75, y: 0.25),
+                    (x: 0.75, y: 0.75),
+                    (x: 0.25, y: 0.75),
+                ],
+                polygon![
+                    (x: 0.375, y: 0.375),
+                    (x: 0.625, y: 0.375),
+                    (x: 0.625, y: 0.625),
+                    (x: 0.375, y: 0.625),
+                ],
+                dot.clone(),
+            ],
+            vec![0, 1, 2, 1],
+            HashMap::from([
+                ("plate".to_string(), vec![vec![0]]),
+                ("letters".to_string(), vec![vec![1, 2]]),
+                ("braille".to_string(), vec![vec![3]]),
+            ]),
+        );
+        let mut options = StlOptions::with_plate("plate", 0.125);
+        options.extrusions.insert(
+            "braille".into(),
+            Extrusion::Dome {
+                base: 0.125,
+                height: 0.025,
+            },
+        );
+        let cubic_mm = 25.4f64.powi(3);
+
+        let plate = read(&groups_to_stl(&data, &["plate"], &options));
+        assert_eq!(plate.len(), 12);
+        assert!((volume(&plate) / cubic_mm - 0.25).abs() < 1e-5);
+
+        // The counter of the letter is left open, and letters are raised 1/32" from the plate
+        let letters = read(&groups_to_stl(&data, &["letters"], &options));
+        assert!((volume(&letters) / cubic_mm - 0.1875 / 32.0).abs() < 1e-5);
+        let bottom = letters
+            .iter()
+            .flatten()
+            .map(|point| point[2])
+            .fold(f64::MAX, f64::min);
+        assert!((bottom - 0.125 * 25.4).abs() < 1e-4);
+        let top = letters
+            .iter()
+            .flatten()
+            .map(|point| point[2])
+            .fold(0.0, f64::max);
+        assert!((top - (0.125 + 1.0 / 32.0) * 25.4).abs() < 1e-4);
+
+        // A dome holds a little over half the cylinder around it
+        let braille = read(&groups_to_stl(&data, &["braille"], &options));
+        let cylinder = dot.unsigned_area() * 0.025;
+        let ratio = volume(&braille) / cubic_mm / cylinder;
+        assert!(ratio > 0.5 && ratio < 0.7);
+        let top = braille
+            .iter()
+            .flatten()
+            .map(|point| point[2])
+            .fold(0.0, f64::max);
+        assert!((top - 0.15 * 25.4).abs() < 1e-4);
+
+        options.ascii = true;
+        let groups = ["plate", "letters", "braille"];
+        let ascii = String::from_utf8(groups_to_stl(&data, &groups, &options)).unwrap();
+        assert!(ascii.starts_with("solid gel\n"));
+        assert!(ascii.ends_with("endsolid gel\n"));
+        assert_eq!(
+            ascii.matches("facet normal").count(),
+            plate.len() + letters.len() + braille.len()
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("gel-save-stl-test-{}.stl", std::process::id()));
+        if let Err(err) = save_stl(&data, &groups, &options, &path) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 use geo::{BoundingRect, MultiPolygon, Polygon, Scale, Translate};
 
 /// Convert a geo::Polygon<f64> into an SVG path string
-fn polygon_to_svg_path(polygon: &Polygon<f64>) -> String {
+pub(crate) fn polygon_to_svg_path(polygon: &Polygon<f64>) -> String {
     let mut d = String::new();
 
     // Exterior ring
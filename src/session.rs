@@ -0,0 +1,236 @@
+use std::{collections::HashMap, sync::Arc};
+
+use geo::{LineString, Polygon, coord};
+use serde::{Deserialize, Serialize};
+
+use crate::{Attributes, Data, Macro, Snapshot};
+
+/// Everything `Data::save` writes. Each shape is a list of rings, the exterior first, and each
+/// ring a list of `[x, y]` points.
+#[derive(Debug, Serialize, Deserialize)]
+struct Session {
+    shapes: Vec<Vec<Vec<[f64; 2]>>>,
+    depths: Vec<usize>,
+    groups: HashMap<String, Vec<Vec<usize>>>,
+    #[serde(default)]
+    attributes: Attributes,
+    #[serde(default)]
+    params: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    macros: HashMap<String, Macro>,
+    #[serde(default)]
+    checkpoints: HashMap<String, Snapshot>,
+}
+
+fn ring_to_points(ring: &LineString) -> Vec<[f64; 2]> {
+    ring.0.iter().map(|point| [point.x, point.y]).collect()
+}
+
+fn points_to_ring(points: Vec<[f64; 2]>) -> LineString {
+    LineString::new(
+        points
+            .into_iter()
+            .map(|[x, y]| coord! {x: x, y: y})
+            .collect(),
+    )
+}
+
+impl Data {
+    /// Writes the shapes, depths, groups, attributes, params, macros and checkpoints to `path`
+    /// as JSON, to be picked up again with `Data::load`.
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        let session = Session {
+            shapes: self
+                .shapes
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|shape| {
+                    std::iter::once(shape.exterior())
+                        .chain(shape.interiors())
+                        .map(ring_to_points)
+                        .collect()
+                })
+                .collect(),
+            depths: self.depths.lock().unwrap().clone(),
+            groups: (**self.groups.lock().unwrap()).clone(),
+            attributes: (**self.attributes.lock().unwrap()).clone(),
+            params: self.params.clone(),
+            macros: self.macros.clone(),
+            checkpoints: self.checkpoints.clone(),
+        };
+
+        let json = serde_json::to_string(&session)
+            .map_err(|err| format!("Could not write session: {}", err))?;
+        std::fs::write(path, json)
+            .map_err(|err| format!("Could not write '{}': {}", path.display(), err))
+    }
+
+    /// Reads a session written by `Data::save` into a new `Data` with a fresh JS context that
+    /// has the saved params set again.
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+        let session: Session = serde_json::from_str(&json)
+            .map_err(|err| format!("Could not read session '{}': {}", path.display(), err))?;
+
+        let shapes: Vec<Polygon> = session
+            .shapes
+            .into_iter()
+            .map(|rings| {
+                let mut rings = rings.into_iter().map(points_to_ring);
+                let exterior = rings.next().unwrap_or_else(|| LineString::new(Vec::new()));
+                Polygon::new(exterior, rings.collect())
+            })
+            .collect();
+        if session.depths.len() != shapes.len() {
+            return Err(format!(
+                "Session has {} depths for {} shapes.",
+                session.depths.len(),
+                shapes.len()
+            ));
+        }
+        for (name, group) in &session.groups {
+            if group.iter().flatten().any(|index| *index >= shapes.len()) {
+                return Err(format!(
+                    "Group '{}' refers to a shape that isn't in the session.",
+                    name
+                ));
+            }
+        }
+
+        for (name, checkpoint) in &session.checkpoints {
+            if checkpoint.shapes_len > shapes.len()
+                || checkpoint.depths.len() != checkpoint.shapes_len
+            {
+                return Err(format!(
+                    "Checkpoint '{}' doesn't match the {} shapes of the session.",
+                    name,
+                    shapes.len()
+                ));
+            }
+        }
+
+        let mut data = Data::from_parts(shapes, session.depths, session.groups);
+        *data.attributes.lock().unwrap() = Arc::new(session.attributes);
+        for (name, value) in session.params {
+            data.set_param(&name, value)?;
+        }
+        for (name, definition) in session.macros {
+            data.define_macro(&name, definition);
+        }
+        data.checkpoints = session.checkpoints;
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, polygon};
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![
+            polygon![
+                exterior: [
+                    (x: 0.0, y: 0.0),
+                    (x: 4.0, y: 0.0),
+                    (x: 4.0, y: 4.0),
+                    (x: 0.0, y: 4.0),
+                ],
+                interiors: [[
+                    (x: 1.0, y: 1.0),
+                    (x: 2.0, y: 1.0),
+                    (x: 2.0, y: 2.0),
+                    (x: 1.0, y: 2.0),
+                ]],
+            ],
+            polygon![
+                (x: 10.0, y: 0.0),
+                (x: 11.0, y: 0.0),
+                (x: 11.0, y: 1.0),
+                (x: 10.0, y: 1.0),
+            ],
+        ]);
+        let queries = vec![
+            Instruction::Let(Let {
+                name: "limit".into(),
+                code: "2".into(),
+            }),
+            Instruction::Filter(Filter {
+                set_group: "big".into(),
+                get_group: "main".into(),
+                code: "area('main', i) > limit".into(),
+            }),
+            Instruction::Map(Map {
+                set_attribute: "area".into(),
+                get_group: "big".into(),
+                code: "area('big', i)".into(),
+            }),
+            Instruction::Checkpoint(Checkpoint {
+                name: "filtered".into(),
+            }),
+        ];
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("gel-session-test-{}.json", std::process::id()));
+        if let Err(err) = data.save(&path) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+        let mut loaded = match Data::load(&path) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+
+        assert_eq!(*loaded.shapes.lock().unwrap(), *data.shapes.lock().unwrap());
+        assert_eq!(*loaded.depths.lock().unwrap(), *data.depths.lock().unwrap());
+        assert_eq!(*loaded.groups.lock().unwrap(), *data.groups.lock().unwrap());
+        assert_eq!(
+            *loaded.attributes.lock().unwrap(),
+            *data.attributes.lock().unwrap()
+        );
+        assert_eq!(loaded.params, data.params);
+        assert!(loaded.checkpoints.contains_key("filtered"));
+
+        // The params and builtins are back in the new context
+        let code = loaded.compile("area('big', 0) - limit", &[]).unwrap();
+        let value = code
+            .call(&[], &mut loaded.context)
+            .unwrap()
+            .to_number(&mut loaded.context)
+            .unwrap();
+        let area = loaded.shapes.lock().unwrap()[loaded.groups.lock().unwrap()["big"][0][0]]
+            .unsigned_area();
+        assert!((value - (area - 2.0)).abs() < 1e-9);
+
+        std::fs::write(
+            &path,
+            r#"{"shapes": [], "depths": [], "groups": {"main": [[0]]}}"#,
+        )
+        .unwrap();
+        assert!(Data::load(&path).is_err());
+
+        // A checkpoint can't keep more shapes than the session has
+        std::fs::write(
+            &path,
+            r#"{"shapes": [], "depths": [], "groups": {}, "checkpoints": {"later": {
+                "shapes_len": 1, "depths": [0], "groups": {}, "attributes": {}, "params": {}
+            }}}"#,
+        )
+        .unwrap();
+        assert!(Data::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
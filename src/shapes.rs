@@ -0,0 +1,144 @@
+use std::{ops::Index, sync::Arc};
+
+use geo::Polygon;
+
+/// The shapes of a `Data`, indexed in the order they were added.
+///
+/// The first ones can be a `base` shared with other `Data`s, which is how the workers of a
+/// parallel `LoopOver` all read the shapes from before the loop without copying them. The
+/// shapes added since are kept apart after the base.
+#[derive(Debug, Clone, Default)]
+pub struct Shapes {
+    base: Arc<Vec<Polygon>>,
+    added: Vec<Polygon>,
+}
+
+impl Shapes {
+    pub fn len(&self) -> usize {
+        self.base.len() + self.added.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Polygon> {
+        match index.checked_sub(self.base.len()) {
+            Some(index) => self.added.get(index),
+            None => self.base.get(index),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Polygon> {
+        self.base.iter().chain(self.added.iter())
+    }
+
+    pub fn push(&mut self, shape: Polygon) {
+        self.added.push(shape);
+    }
+
+    /// Moves every shape of `shapes` after these, leaving it empty.
+    pub fn append(&mut self, shapes: &mut Vec<Polygon>) {
+        self.added.append(shapes);
+    }
+
+    /// Keeps the first `len` shapes. Only copies the base when cutting into it while it's shared.
+    pub fn truncate(&mut self, len: usize) {
+        match len.checked_sub(self.base.len()) {
+            Some(len) => self.added.truncate(len),
+            None => {
+                self.added.clear();
+                Arc::make_mut(&mut self.base).truncate(len);
+            }
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<Polygon> {
+        self.iter().cloned().collect()
+    }
+
+    /// Makes every shape part of the base and returns it, to be shared with other `Shapes`.
+    pub fn share(&mut self) -> Arc<Vec<Polygon>> {
+        if !self.added.is_empty() {
+            Arc::make_mut(&mut self.base).append(&mut self.added);
+        }
+        self.base.clone()
+    }
+
+    /// The shapes after the base.
+    pub fn added(&self) -> &[Polygon] {
+        &self.added
+    }
+}
+
+impl Index<usize> for Shapes {
+    type Output = Polygon;
+
+    fn index(&self, index: usize) -> &Polygon {
+        self.get(index).expect("shape index out of bounds")
+    }
+}
+
+impl PartialEq for Shapes {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl From<Vec<Polygon>> for Shapes {
+    fn from(value: Vec<Polygon>) -> Self {
+        Self {
+            base: Arc::default(),
+            added: value,
+        }
+    }
+}
+
+impl From<Arc<Vec<Polygon>>> for Shapes {
+    fn from(value: Arc<Vec<Polygon>>) -> Self {
+        Self {
+            base: value,
+            added: Vec::new(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Shapes {
+    type Item = &'a Polygon;
+    type IntoIter = std::iter::Chain<std::slice::Iter<'a, Polygon>, std::slice::Iter<'a, Polygon>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.base.iter().chain(self.added.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let square = |x: f64| polygon![(x: x, y: 0.0), (x: x + 1.0, y: 0.0), (x: x, y: 1.0)];
+        let mut shapes = Shapes::from(vec![square(0.0), square(1.0)]);
+
+        let base = shapes.share();
+        let mut other = Shapes::from(base.clone());
+        shapes.push(square(2.0));
+        other.push(square(3.0));
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(shapes[2], square(2.0));
+        assert_eq!(other[2], square(3.0));
+        assert!(shapes.added() == [square(2.0)]);
+
+        // Cutting into a shared base leaves the others sharing it alone
+        other.truncate(1);
+        assert_eq!(other.to_vec(), vec![square(0.0)]);
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(base.len(), 2);
+
+        assert_eq!(Shapes::from(shapes.to_vec()), shapes);
+        assert_eq!(shapes.get(3), None);
+    }
+}
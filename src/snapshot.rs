@@ -0,0 +1,71 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Attributes, Data, Groups};
+
+/// The state of a `Data` at some point, made by `Data::snapshot`.
+///
+/// Queries only ever append to `shapes`, so a snapshot keeps the number of shapes instead of
+/// a copy of them, and restoring drops whatever was appended since. Groups and attributes are
+/// shared with the `Data` until either side changes them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub(crate) shapes_len: usize,
+    pub(crate) depths: Vec<usize>,
+    groups: Arc<Groups>,
+    attributes: Arc<Attributes>,
+    params: HashMap<String, serde_json::Value>,
+}
+
+impl Data {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            shapes_len: self.shapes.lock().unwrap().len(),
+            depths: self.depths.lock().unwrap().clone(),
+            groups: self.groups.lock().unwrap().clone(),
+            attributes: self.attributes.lock().unwrap().clone(),
+            params: self.params.clone(),
+        }
+    }
+
+    /// Puts the data back as it was when `snapshot` was taken.
+    ///
+    /// Checkpoints holding more shapes than the snapshot are dropped, since the shapes they
+    /// kept are gone and new ones will take their place.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        {
+            let mut shapes = self.shapes.lock().unwrap();
+            if shapes.len() < snapshot.shapes_len {
+                return Err(format!(
+                    "Can't restore {} shapes when only {} are left.",
+                    snapshot.shapes_len,
+                    shapes.len()
+                ));
+            }
+            shapes.truncate(snapshot.shapes_len);
+        }
+        self.checkpoints
+            .retain(|_, checkpoint| checkpoint.shapes_len <= snapshot.shapes_len);
+        *self.depths.lock().unwrap() = snapshot.depths;
+        *self.groups.lock().unwrap() = snapshot.groups;
+        *self.attributes.lock().unwrap() = snapshot.attributes;
+
+        let added = self
+            .params
+            .keys()
+            .filter(|name| !snapshot.params.contains_key(*name))
+            .cloned()
+            .collect::<Vec<String>>();
+        for name in added {
+            self.remove_param(&name)?;
+        }
+        for (name, value) in snapshot.params {
+            if self.params.get(&name) != Some(&value) {
+                self.set_param(&name, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use std::sync::{Arc, Mutex};
+
+use boa_engine::{Context, JsResult, JsValue, NativeFunction};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::Data;
+
+/// A trace shared by a `Data`, its compiled expressions and its builtins. `None` while
+/// nothing is being traced.
+pub(crate) type SharedTrace = Arc<Mutex<Option<Trace>>>;
+
+/// A call to a builtin such as `frame` made while an expression was evaluated.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuiltinCall {
+    pub name: String,
+    pub args: Vec<Value>,
+    pub result: Value,
+}
+
+/// One call of a compiled expression.
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    pub code: String,
+    pub args: Vec<Value>,
+    pub result: Value,
+    pub builtins: Vec<BuiltinCall>,
+}
+
+/// How `Kerning` moved the shapes of one sub-group.
+#[derive(Debug, Clone, Serialize)]
+pub struct KerningStep {
+    pub horizontal: bool,
+    pub direction: String,
+    /// How far each shape of the kerned sub-group moved, in the order of the `shapes` of the
+    /// element.
+    pub offsets: Vec<[f64; 2]>,
+}
+
+/// What a traced query decided about one sub-group of its `get_group`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TracedElement {
+    /// The index of the sub-group in `get_group`.
+    pub index: usize,
+    pub shapes: Vec<usize>,
+    pub evaluations: Vec<Evaluation>,
+    /// The sub-group of `set_group` it ended up in, if it was kept.
+    pub joined: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kerning: Option<KerningStep>,
+}
+
+/// Everything one run of a `Filter`, `GroupBy` or `Kerning` did.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryTrace {
+    pub query: String,
+    pub get_group: String,
+    pub set_group: String,
+    /// Evaluations made before the first element, like the `space` of `Kerning`.
+    pub evaluations: Vec<Evaluation>,
+    pub elements: Vec<TracedElement>,
+}
+
+/// The record made between `Data::start_trace` and `Data::take_trace`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Trace {
+    pub queries: Vec<QueryTrace>,
+    #[serde(skip)]
+    calls: Vec<BuiltinCall>,
+    #[serde(skip)]
+    pending: Vec<Evaluation>,
+}
+
+impl Trace {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|err| format!("Could not write trace: {}", err))
+    }
+
+    /// The query trace of the last `Kerning` that set `set_group`.
+    pub fn kerning(&self, set_group: &str) -> Option<&QueryTrace> {
+        self.queries
+            .iter()
+            .rev()
+            .find(|query| query.query == "Kerning" && query.set_group == set_group)
+    }
+}
+
+fn to_json(value: &JsValue, context: &mut Context) -> Value {
+    value.to_json(context).unwrap_or(Value::Null)
+}
+
+/// Calls `call` and, while tracing, records it as an evaluation of `code` together with the
+/// builtins it called.
+pub(crate) fn evaluate(
+    trace: &SharedTrace,
+    code: &str,
+    args: &[JsValue],
+    context: &mut Context,
+    call: impl FnOnce(&mut Context) -> JsResult<JsValue>,
+) -> JsResult<JsValue> {
+    if trace.lock().unwrap().is_none() {
+        return call(context);
+    }
+
+    let outer = trace
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(|trace| std::mem::take(&mut trace.calls));
+    let result = call(context);
+
+    let evaluation = Evaluation {
+        code: code.to_string(),
+        args: args.iter().map(|arg| to_json(arg, context)).collect(),
+        result: match &result {
+            Ok(value) => to_json(value, context),
+            Err(err) => Value::String(format!("Error: {}", err)),
+        },
+        builtins: Vec::new(),
+    };
+    if let Some(trace) = trace.lock().unwrap().as_mut() {
+        let builtins = std::mem::replace(&mut trace.calls, outer.unwrap_or_default());
+        trace.pending.push(Evaluation {
+            builtins,
+            ..evaluation
+        });
+    }
+
+    result
+}
+
+/// Wraps a builtin so that its calls are recorded while tracing.
+///
+/// # Safety
+///
+/// Same as `NativeFunction::from_closure`, `builtin` must not capture any traced JS values.
+pub(crate) unsafe fn traced<F>(
+    trace: &SharedTrace,
+    name: &'static str,
+    builtin: F,
+) -> NativeFunction
+where
+    F: Fn(&JsValue, &[JsValue], &mut Context) -> JsResult<JsValue> + 'static,
+{
+    let trace = trace.clone();
+    unsafe {
+        NativeFunction::from_closure(
+            move |this: &JsValue, args: &[JsValue], context: &mut Context| {
+                let result = builtin(this, args, context);
+                if trace.lock().unwrap().is_some() {
+                    let call = BuiltinCall {
+                        name: name.to_string(),
+                        args: args.iter().map(|arg| to_json(arg, context)).collect(),
+                        result: match &result {
+                            Ok(value) => to_json(value, context),
+                            Err(err) => Value::String(format!("Error: {}", err)),
+                        },
+                    };
+                    if let Some(trace) = trace.lock().unwrap().as_mut() {
+                        trace.calls.push(call);
+                    }
+                }
+                result
+            },
+        )
+    }
+}
+
+impl Data {
+    /// Records what `Filter`, `GroupBy` and `Kerning` decide from now on. Iterations of a
+    /// parallel `LoopOver` run in contexts of their own and aren't traced.
+    pub fn start_trace(&mut self) {
+        *self.trace.lock().unwrap() = Some(Trace::default());
+    }
+
+    /// Stops tracing and returns what was recorded.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.lock().unwrap().take()
+    }
+
+    /// Starts the trace of a query, taking the evaluations it made so far.
+    pub(crate) fn trace_query(&self, query: &str, get_group: &str, set_group: &str) {
+        if let Some(trace) = self.trace.lock().unwrap().as_mut() {
+            let evaluations = std::mem::take(&mut trace.pending);
+            trace.queries.push(QueryTrace {
+                query: query.to_string(),
+                get_group: get_group.to_string(),
+                set_group: set_group.to_string(),
+                evaluations,
+                elements: Vec::new(),
+            });
+        }
+    }
+
+    /// Adds an element to the current query trace with the evaluations made for it.
+    pub(crate) fn trace_element(
+        &self,
+        index: usize,
+        shapes: &[usize],
+        joined: Option<usize>,
+        kerning: Option<KerningStep>,
+    ) {
+        if let Some(trace) = self.trace.lock().unwrap().as_mut() {
+            let evaluations = std::mem::take(&mut trace.pending);
+            if let Some(query) = trace.queries.last_mut() {
+                query.elements.push(TracedElement {
+                    index,
+                    shapes: shapes.to_vec(),
+                    evaluations,
+                    joined,
+                    kerning,
+                });
+            }
+        }
+    }
+
+    /// Drops evaluations no traced query claimed, such as the ones of a `Let`.
+    pub(crate) fn clear_pending_trace(&self) {
+        if let Some(trace) = self.trace.lock().unwrap().as_mut() {
+            trace.pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::polygon;
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut data = Data::from(vec![
+            polygon![
+                (x: 0.0, y: 0.0),
+                (x: 1.0, y: 0.0),
+                (x: 1.0, y: 1.0),
+                (x: 0.0, y: 1.0),
+            ],
+            polygon![
+                (x: 5.0, y: 0.0),
+                (x: 8.0, y: 0.0),
+                (x: 8.0, y: 3.0),
+                (x: 5.0, y: 3.0),
+            ],
+        ]);
+        data.start_trace();
+
+        let queries = vec![
+            Instruction::Let(Let {
+                name: "limit".into(),
+                code: "2".into(),
+            }),
+            Instruction::Filter(Filter {
+                set_group: "big".into(),
+                get_group: "main".into(),
+                code: "frame('main', i, 0).width > limit".into(),
+            }),
+        ];
+        if let Err(err) = data.query(queries) {
+            println!("Error: {}", err);
+            assert!(false);
+        }
+
+        let trace = data.take_trace().unwrap();
+        assert_eq!(trace.queries.len(), 1);
+
+        let filter = &trace.queries[0];
+        assert_eq!(filter.query, "Filter");
+        assert!(filter.evaluations.is_empty());
+        assert_eq!(filter.elements.len(), 2);
+
+        let big = data.groups.lock().unwrap()["big"].clone();
+        for element in &filter.elements {
+            assert_eq!(element.evaluations.len(), 1);
+
+            let evaluation = &element.evaluations[0];
+            assert_eq!(evaluation.args, vec![serde_json::json!(element.index)]);
+            assert_eq!(evaluation.builtins.len(), 1);
+            assert_eq!(evaluation.builtins[0].name, "frame");
+
+            let width = evaluation.builtins[0].result["width"].as_f64().unwrap();
+            assert_eq!(evaluation.result, serde_json::json!(width > 2.0));
+            assert_eq!(element.joined.is_some(), width > 2.0);
+            if let Some(joined) = element.joined {
+                assert_eq!(big[joined], element.shapes);
+            }
+        }
+
+        assert!(trace.to_json().is_ok());
+        assert!(data.take_trace().is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use geo::{Coord, LineString, Orient, Polygon, orient::Direction};
+
+/// Twice the signed area of the triangle `a`, `b`, `c`, positive when counter clockwise.
+fn cross(a: Coord, b: Coord, c: Coord) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// The points of a closed ring without repeated neighbours or the repeated last point.
+fn points(ring: &LineString) -> Vec<Coord> {
+    let mut points = ring.0.clone();
+    points.dedup();
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    points
+}
+
+/// Whether the segments `a` `b` and `c` `d` touch anywhere but at an end they share.
+fn crosses(a: Coord, b: Coord, c: Coord, d: Coord) -> bool {
+    if a == c || a == d || b == c || b == d {
+        return false;
+    }
+    let (ab_c, ab_d) = (cross(a, b, c), cross(a, b, d));
+    let (cd_a, cd_b) = (cross(c, d, a), cross(c, d, b));
+    if ab_c == 0.0 && ab_d == 0.0 {
+        // Collinear, so only overlapping extents touch
+        return a.x.min(b.x) <= c.x.max(d.x)
+            && c.x.min(d.x) <= a.x.max(b.x)
+            && a.y.min(b.y) <= c.y.max(d.y)
+            && c.y.min(d.y) <= a.y.max(b.y);
+    }
+    ab_c * ab_d <= 0.0 && cd_a * cd_b <= 0.0
+}
+
+/// Whether `point` is on the inside of the corner at `ring[index]` of a counter clockwise ring.
+fn locally_inside(ring: &[Coord], index: usize, point: Coord) -> bool {
+    let previous = ring[(index + ring.len() - 1) % ring.len()];
+    let (corner, next) = (ring[index], ring[(index + 1) % ring.len()]);
+    if cross(previous, corner, next) >= 0.0 {
+        cross(previous, corner, point) >= 0.0 && cross(corner, next, point) >= 0.0
+    } else {
+        cross(previous, corner, point) >= 0.0 || cross(corner, next, point) >= 0.0
+    }
+}
+
+fn edges(ring: &[Coord]) -> impl Iterator<Item = (Coord, Coord)> + '_ {
+    (0..ring.len()).map(|index| (ring[index], ring[(index + 1) % ring.len()]))
+}
+
+/// Joins a clockwise `hole` into the counter clockwise `outer` ring through a pair of
+/// overlapping edges from its rightmost point to the nearest outer point it can see.
+fn bridge(outer: &mut Vec<Coord>, hole: &[Coord], others: &[Vec<Coord>]) {
+    let (start, from) = hole
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.x.total_cmp(&b.1.x))
+        .unwrap();
+
+    let mut candidates: Vec<usize> = (0..outer.len()).collect();
+    candidates.sort_by(|a, b| {
+        let (a, b) = (outer[*a] - from, outer[*b] - from);
+        a.x.hypot(a.y).total_cmp(&b.x.hypot(b.y))
+    });
+    let visible = candidates.iter().copied().find(|index| {
+        let to = outer[*index];
+        locally_inside(outer, *index, from)
+            && !edges(outer)
+                .chain(edges(hole))
+                .chain(others.iter().flat_map(|other| edges(other)))
+                .any(|(a, b)| crosses(from, to, a, b))
+    });
+    let index = visible.unwrap_or(candidates[0]);
+
+    let mut joined = outer[..=index].to_vec();
+    joined.extend(&hole[start..]);
+    joined.extend(&hole[..=start]);
+    joined.extend(&outer[index..]);
+    *outer = joined;
+}
+
+fn is_ear(ring: &[Coord], index: usize) -> bool {
+    let a = ring[(index + ring.len() - 1) % ring.len()];
+    let (b, c) = (ring[index], ring[(index + 1) % ring.len()]);
+    cross(a, b, c) > 0.0
+        && ring.iter().all(|point| {
+            *point == a
+                || *point == b
+                || *point == c
+                || cross(a, b, *point) < 0.0
+                || cross(b, c, *point) < 0.0
+                || cross(c, a, *point) < 0.0
+        })
+}
+
+/// Splits `polygon` and its holes into counter clockwise triangles by ear clipping, after
+/// bridging every hole into the exterior ring.
+pub(crate) fn triangulate(polygon: &Polygon) -> Vec<[Coord; 3]> {
+    let polygon = polygon.orient(Direction::Default);
+    let mut ring = points(polygon.exterior());
+    if ring.len() < 3 {
+        return Vec::new();
+    }
+
+    let max_x = |hole: &Vec<Coord>| hole.iter().map(|point| point.x).fold(f64::MIN, f64::max);
+    let mut holes: Vec<Vec<Coord>> = polygon
+        .interiors()
+        .iter()
+        .map(points)
+        .filter(|hole| hole.len() >= 3)
+        .collect();
+    holes.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+    for (n, hole) in holes.iter().enumerate() {
+        bridge(&mut ring, hole, &holes[n + 1..]);
+    }
+
+    let mut triangles = Vec::with_capacity(ring.len());
+    let mut start = 0;
+    while ring.len() > 3 {
+        let len = ring.len();
+        // Without an ear left only slivers remain, so drop the flattest corner
+        let index = (start..start + len)
+            .map(|index| index % len)
+            .find(|index| is_ear(&ring, *index))
+            .unwrap_or_else(|| {
+                (0..len)
+                    .min_by(|a, b| {
+                        let flatness = |index: usize| {
+                            cross(
+                                ring[(index + len - 1) % len],
+                                ring[index],
+                                ring[(index + 1) % len],
+                            )
+                            .abs()
+                        };
+                        flatness(*a).total_cmp(&flatness(*b))
+                    })
+                    .unwrap()
+            });
+
+        let (a, b, c) = (
+            ring[(index + len - 1) % len],
+            ring[index],
+            ring[(index + 1) % len],
+        );
+        if cross(a, b, c) > 0.0 {
+            triangles.push([a, b, c]);
+        }
+        ring.remove(index);
+        start = index;
+    }
+    if cross(ring[0], ring[1], ring[2]) > 0.0 {
+        triangles.push([ring[0], ring[1], ring[2]]);
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, polygon};
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let area = |triangles: &[[Coord; 3]]| -> f64 {
+            triangles
+                .iter()
+                .map(|[a, b, c]| {
+                    assert!(cross(*a, *b, *c) > 0.0);
+                    cross(*a, *b, *c) / 2.0
+                })
+                .sum()
+        };
+
+        // An L, clockwise, so it has a reflex corner
+        let l = polygon![
+            (x: 0.0, y: 0.0),
+            (x: 0.0, y: 2.0),
+            (x: 1.0, y: 2.0),
+            (x: 1.0, y: 1.0),
+            (x: 2.0, y: 1.0),
+            (x: 2.0, y: 0.0),
+        ];
+        let triangles = triangulate(&l);
+        assert_eq!(triangles.len(), 4);
+        assert!((area(&triangles) - 3.0).abs() < 1e-9);
+
+        // A plate with two counters side by side
+        let plate = polygon![
+            exterior: [
+                (x: 0.0, y: 0.0),
+                (x: 6.0, y: 0.0),
+                (x: 6.0, y: 3.0),
+                (x: 0.0, y: 3.0),
+            ],
+            interiors: [
+                [
+                    (x: 1.0, y: 1.0),
+                    (x: 2.0, y: 1.0),
+                    (x: 2.0, y: 2.0),
+                    (x: 1.0, y: 2.0),
+                ],
+                [
+                    (x: 4.0, y: 1.0),
+                    (x: 5.0, y: 1.5),
+                    (x: 4.0, y: 2.0),
+                ],
+            ],
+        ];
+        let triangles = triangulate(&plate);
+        assert!((area(&triangles) - plate.unsigned_area()).abs() < 1e-9);
+        // No triangle covers a counter
+        for [a, b, c] in &triangles {
+            let center = (*a + *b + *c) / 3.0;
+            assert!(!(center.x > 1.0 && center.x < 2.0 && center.y > 1.0 && center.y < 2.0));
+        }
+    }
+}
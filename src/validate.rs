@@ -0,0 +1,905 @@
+use std::collections::{HashMap, HashSet};
+
+use boa_engine::{Context, Script, Source};
+
+use crate::{Instruction, Macro, Pipeline, Query, expression::function_source};
+
+/// Builtins that take a group name as their first argument. `distance` takes two.
+const GROUP_BUILTINS: [&str; 8] = [
+    "area",
+    "group_index",
+    "frame",
+    "len",
+    "center",
+    "circle_metrics",
+    "distance",
+    "attr",
+];
+
+/// A problem found by `Pipeline::validate`. `at` is the path of the query, such as
+/// `queries[2].then[0]`, and `query` its name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The group is read before any query sets it.
+    UndefinedGroup {
+        group: String,
+        query: String,
+        at: String,
+    },
+    /// A condition reads the group before any query sets it for sure, which is fine when
+    /// the condition checks that it exists first.
+    MaybeUndefinedGroup {
+        group: String,
+        query: String,
+        at: String,
+    },
+    /// The group is set but nothing reads it afterwards.
+    UnusedGroup {
+        group: String,
+        query: String,
+        at: String,
+    },
+    /// A `Call` names a macro the pipeline doesn't define.
+    UndefinedMacro {
+        name: String,
+        query: String,
+        at: String,
+    },
+    SyntaxError {
+        code: String,
+        message: String,
+        query: String,
+        at: String,
+    },
+    /// A parallel `LoopOver` whose iterations read what earlier ones wrote, which only a
+    /// sequential loop passes on. `reason` says what, see `iteration_dependency`.
+    DependentIterations {
+        reason: String,
+        query: String,
+        at: String,
+    },
+}
+
+impl Issue {
+    /// Whether the pipeline is going to fail because of this. Unused groups and groups read
+    /// by conditions are only suspicious.
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Issue::UnusedGroup { .. } | Issue::MaybeUndefinedGroup { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::UndefinedGroup { group, query, at } => {
+                write!(f, "{} at {} reads '{}' before it is set.", query, at, group)
+            }
+            Issue::MaybeUndefinedGroup { group, query, at } => {
+                write!(
+                    f,
+                    "{} at {} reads '{}' which may not be set yet.",
+                    query, at, group
+                )
+            }
+            Issue::UnusedGroup { group, query, at } => {
+                write!(
+                    f,
+                    "{} at {} sets '{}' but it is never read.",
+                    query, at, group
+                )
+            }
+            Issue::UndefinedMacro { name, query, at } => {
+                write!(f, "{} at {} calls the unknown macro '{}'.", query, at, name)
+            }
+            Issue::SyntaxError {
+                code,
+                message,
+                query,
+                at,
+            } => write!(
+                f,
+                "{} at {} could not compile '{}': {}",
+                query, at, code, message
+            ),
+            Issue::DependentIterations { reason, query, at } => {
+                write!(
+                    f,
+                    "{} at {} can't run in parallel because {}.",
+                    query, at, reason
+                )
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    /// Checks the pipeline without running it.
+    ///
+    /// Only `main` exists before the first query, so any group the caller reads once the
+    /// pipeline is done shows up as unused, see `Pipeline::validate_with_outputs`.
+    pub fn validate(&self) -> Vec<Issue> {
+        self.validate_with_outputs(&[])
+    }
+
+    /// Like `Pipeline::validate`, with `outputs` counted as read after the last query.
+    pub fn validate_with_outputs(&self, outputs: &[&str]) -> Vec<Issue> {
+        let mut validator = Validator {
+            macros: &self.macros,
+            summaries: HashMap::new(),
+            context: Context::default(),
+            issues: Vec::new(),
+            strict: false,
+        };
+
+        let mut names: Vec<&String> = self.macros.keys().collect();
+        names.sort();
+        for name in names {
+            validator.summarize(name);
+        }
+
+        let mut scope = Scope::default();
+        scope.defined.insert("main".to_string());
+        for (index, instruction) in self.queries.iter().enumerate() {
+            validator.instruction(instruction, &format!("queries[{}]", index), &mut scope);
+        }
+        for output in outputs {
+            scope.read.insert(output.to_string());
+        }
+        validator.unused(&scope);
+
+        validator.issues
+    }
+}
+
+/// Why the iterations of a loop running `instructions` can't run apart from each other:
+/// something an iteration reads before setting it, which it would get from the iteration
+/// before when the loop runs in order. `None` when every iteration only sees what the loop
+/// and the iteration itself set.
+pub(crate) fn iteration_dependency<'a>(
+    instructions: impl IntoIterator<Item = &'a Instruction>,
+    iterator_name: &str,
+    index_name: Option<&str>,
+    macros: &HashMap<String, Macro>,
+) -> Option<String> {
+    let mut validator = Validator {
+        macros,
+        summaries: HashMap::new(),
+        context: Context::default(),
+        issues: Vec::new(),
+        strict: true,
+    };
+
+    let mut scope = Scope::default();
+    scope.defined.insert(iterator_name.to_string());
+    if let Some(index_name) = index_name {
+        scope.known.insert(State::Param(index_name.to_string()));
+    }
+    for (index, instruction) in instructions.into_iter().enumerate() {
+        validator.instruction(instruction, &format!("instructions[{}]", index), &mut scope);
+    }
+
+    let state = scope
+        .early
+        .iter()
+        .find(|state| scope.written.contains(*state))?;
+    Some(match state {
+        State::Group(group) => format!("iterations read '{}' before setting it", group),
+        State::Attributes(group) => format!(
+            "iterations read the attributes of '{}' before mapping them",
+            group
+        ),
+        State::Param(name) => format!("iterations read '{}' before their Let sets it", name),
+        State::Depths => "iterations read depths that they recompute".to_string(),
+        State::Checkpoints => "iterations save or roll back checkpoints".to_string(),
+    })
+}
+
+/// Something a query reads or writes, for `iteration_dependency`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum State {
+    Group(String),
+    Attributes(String),
+    Param(String),
+    Depths,
+    Checkpoints,
+}
+
+/// The groups of a macro that matter to its callers.
+#[derive(Debug, Clone, Default)]
+struct Summary {
+    /// Params read before the macro sets them, which the caller has to have set.
+    needs: HashSet<String>,
+    /// Params the macro sets, which are copied back to the caller.
+    sets: HashSet<String>,
+}
+
+/// The groups of the pipeline or of one macro.
+#[derive(Debug, Default)]
+struct Scope {
+    defined: HashSet<String>,
+    read: HashSet<String>,
+    /// Every group set in the scope with the first query that set it.
+    sets: Vec<(String, String, String)>,
+    /// The params when the scope is a macro.
+    params: Vec<String>,
+    needs: HashSet<String>,
+    /// Everything read before the scope wrote it, in order.
+    early: Vec<State>,
+    /// Everything the scope writes.
+    written: HashSet<State>,
+    /// The attributes and params written so far, like `defined` is for groups.
+    known: HashSet<State>,
+}
+
+struct Validator<'a> {
+    macros: &'a HashMap<String, Macro>,
+    /// `None` while the macro is being summarized, so recursive calls stop there.
+    summaries: HashMap<String, Option<Summary>>,
+    context: Context,
+    issues: Vec<Issue>,
+    /// Only counts what every path sets as set: both branches of an `If`, and never the
+    /// body of a `While` or a `LoopOver`, which may not run.
+    strict: bool,
+}
+
+impl Validator<'_> {
+    fn summarize(&mut self, name: &str) -> Option<Summary> {
+        if let Some(summary) = self.summaries.get(name) {
+            return summary.clone();
+        }
+        let definition = self.macros.get(name)?;
+        self.summaries.insert(name.to_string(), None);
+
+        let mut scope = Scope {
+            params: definition.params.clone(),
+            ..Scope::default()
+        };
+        for (index, instruction) in definition.instructions.iter().enumerate() {
+            self.instruction(
+                instruction,
+                &format!("macros.{}.instructions[{}]", name, index),
+                &mut scope,
+            );
+        }
+        self.unused(&scope);
+
+        let summary = Summary {
+            sets: scope
+                .params
+                .iter()
+                .filter(|param| scope.defined.contains(*param))
+                .cloned()
+                .collect(),
+            needs: scope.needs,
+        };
+        self.summaries
+            .insert(name.to_string(), Some(summary.clone()));
+        Some(summary)
+    }
+
+    fn unused(&mut self, scope: &Scope) {
+        for (group, query, at) in &scope.sets {
+            if !scope.read.contains(group) && !scope.params.contains(group) {
+                self.issues.push(Issue::UnusedGroup {
+                    group: group.clone(),
+                    query: query.clone(),
+                    at: at.clone(),
+                });
+            }
+        }
+    }
+
+    fn read(&mut self, group: &str, query: &str, at: &str, scope: &mut Scope) {
+        scope.read.insert(group.to_string());
+        if scope.defined.contains(group) {
+            return;
+        }
+        scope.early.push(State::Group(group.to_string()));
+        if scope.params.iter().any(|param| param == group) {
+            scope.needs.insert(group.to_string());
+            return;
+        }
+        self.issues.push(Issue::UndefinedGroup {
+            group: group.to_string(),
+            query: query.to_string(),
+            at: at.to_string(),
+        });
+        // Only report the first read
+        scope.defined.insert(group.to_string());
+    }
+
+    /// Like `read`, for reads that don't fail when the group isn't set. `warn` still reports
+    /// those, for reads that only might not fail.
+    fn read_optional(&mut self, group: &str, warn: bool, query: &str, at: &str, scope: &mut Scope) {
+        scope.read.insert(group.to_string());
+        if !scope.defined.contains(group) {
+            scope.early.push(State::Group(group.to_string()));
+        }
+        if !warn || scope.defined.contains(group) || scope.params.iter().any(|param| param == group)
+        {
+            return;
+        }
+        self.issues.push(Issue::MaybeUndefinedGroup {
+            group: group.to_string(),
+            query: query.to_string(),
+            at: at.to_string(),
+        });
+    }
+
+    fn set(&mut self, group: &str, query: &str, at: &str, scope: &mut Scope) {
+        scope.written.insert(State::Group(group.to_string()));
+        // Setting a group drops its attributes
+        scope.known.insert(State::Attributes(group.to_string()));
+        if scope.defined.insert(group.to_string())
+            && !scope.sets.iter().any(|(name, _, _)| name == group)
+        {
+            scope
+                .sets
+                .push((group.to_string(), query.to_string(), at.to_string()));
+        }
+    }
+
+    /// Checks that `code` parses and reads the groups it names in builtin calls.
+    fn code(&mut self, code: &str, params: &[&str], query: &str, at: &str, scope: &mut Scope) {
+        self.parse(code, params, false, query, at, scope);
+    }
+
+    /// Like `code` for the condition of an `If` or a `While`, which may guard a group that
+    /// isn't set yet.
+    fn condition(&mut self, code: &str, query: &str, at: &str, scope: &mut Scope) {
+        self.parse(code, &[], true, query, at, scope);
+    }
+
+    fn parse(
+        &mut self,
+        code: &str,
+        params: &[&str],
+        condition: bool,
+        query: &str,
+        at: &str,
+        scope: &mut Scope,
+    ) {
+        let source = function_source(code, params);
+        if let Err(err) = Script::parse(Source::from_bytes(&source), None, &mut self.context) {
+            self.issues.push(Issue::SyntaxError {
+                code: code.to_string(),
+                message: err.to_string(),
+                query: query.to_string(),
+                at: at.to_string(),
+            });
+            return;
+        }
+
+        for (builtin, group, optional) in group_literals(code) {
+            if builtin == "attr" && !scope.known.contains(&State::Attributes(group.clone())) {
+                scope.early.push(State::Attributes(group.clone()));
+            }
+            if optional || condition {
+                self.read_optional(&group, !optional, query, at, scope);
+            } else {
+                self.read(&group, query, at, scope);
+            }
+        }
+
+        for name in identifiers(code) {
+            let state = if name == "depth" {
+                State::Depths
+            } else {
+                State::Param(name)
+            };
+            if !scope.known.contains(&state) {
+                scope.early.push(state);
+            }
+        }
+    }
+
+    /// Writes `state` for `iteration_dependency`.
+    fn write(&mut self, state: State, scope: &mut Scope) {
+        scope.written.insert(state.clone());
+        scope.known.insert(state);
+    }
+
+    fn instructions(&mut self, instructions: &[Instruction], at: &str, scope: &mut Scope) {
+        for (index, instruction) in instructions.iter().enumerate() {
+            self.instruction(instruction, &format!("{}[{}]", at, index), scope);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, at: &str, scope: &mut Scope) {
+        let query = instruction.name();
+        let query = query.as_str();
+
+        match instruction {
+            Instruction::GroupBy(groupby) => {
+                self.read(&groupby.get_group, query, at, scope);
+                self.code(&groupby.code, &["i", "j"], query, at, scope);
+                self.set(&groupby.set_group, query, at, scope);
+            }
+            Instruction::Filter(filter) => {
+                self.read(&filter.get_group, query, at, scope);
+                self.code(&filter.code, &["i"], query, at, scope);
+                self.set(&filter.set_group, query, at, scope);
+            }
+            Instruction::Sort(sort) => {
+                self.read(&sort.get_group, query, at, scope);
+                for sort_key in &sort.keys {
+                    self.code(&sort_key.key, &["g", "i"], query, at, scope);
+                }
+                if !sort.compare.trim().is_empty() {
+                    self.code(&sort.compare, &["l", "r"], query, at, scope);
+                }
+                self.set(&sort.set_group, query, at, scope);
+            }
+            Instruction::Transformation(transformation) => {
+                // The new shapes keep the depths of the ones they are made from
+                scope.early.push(State::Depths);
+                self.read(&transformation.get_group, query, at, scope);
+                for code in &transformation.transformation {
+                    self.code(code, &[], query, at, scope);
+                }
+                self.set(&transformation.set_group, query, at, scope);
+            }
+            Instruction::Kerning(kerning) => {
+                scope.early.push(State::Depths);
+                self.read(&kerning.get_group, query, at, scope);
+                self.read(&kerning.borders_group, query, at, scope);
+                self.read(&kerning.get_inner_shapes, query, at, scope);
+                self.code(&kerning.space, &[], query, at, scope);
+                self.code(&kerning.epsilon, &[], query, at, scope);
+                self.code(&kerning.respect_space, &["i", "j"], query, at, scope);
+                self.set(&kerning.set_group, query, at, scope);
+                self.set(&kerning.set_inner_shapes, query, at, scope);
+            }
+            Instruction::RecomputeDepths(recompute) => {
+                self.read(&recompute.get_group, query, at, scope);
+                scope.written.insert(State::Depths);
+            }
+            Instruction::GroupUnion(union) => {
+                self.read(&union.get_group, query, at, scope);
+                self.read(&union.other_group, query, at, scope);
+                self.set(&union.set_group, query, at, scope);
+            }
+            Instruction::GroupIntersect(intersect) => {
+                self.read(&intersect.get_group, query, at, scope);
+                self.read(&intersect.other_group, query, at, scope);
+                self.set(&intersect.set_group, query, at, scope);
+            }
+            Instruction::GroupDifference(difference) => {
+                self.read(&difference.get_group, query, at, scope);
+                self.read(&difference.other_group, query, at, scope);
+                self.set(&difference.set_group, query, at, scope);
+            }
+            Instruction::Flatten(flatten) => {
+                self.read(&flatten.get_group, query, at, scope);
+                self.set(&flatten.set_group, query, at, scope);
+            }
+            Instruction::Explode(explode) => {
+                self.read(&explode.get_group, query, at, scope);
+                self.set(&explode.set_group, query, at, scope);
+            }
+            Instruction::Concat(concat) => {
+                for group in &concat.get_groups {
+                    self.read(group, query, at, scope);
+                }
+                self.set(&concat.set_group, query, at, scope);
+            }
+            Instruction::Map(map) => {
+                self.read(&map.get_group, query, at, scope);
+                self.code(&map.code, &["i"], query, at, scope);
+                self.write(State::Attributes(map.get_group.clone()), scope);
+            }
+            Instruction::Let(let_param) => {
+                self.code(&let_param.code, &[], query, at, scope);
+                self.write(State::Param(let_param.name.clone()), scope);
+            }
+            Instruction::If(if_query) => {
+                self.condition(&if_query.condition, query, at, scope);
+
+                // A group set in either branch counts as set afterwards, unless strict
+                let before = (scope.defined.clone(), scope.known.clone());
+                self.instructions(&if_query.then, &format!("{}.then", at), scope);
+                let then_defined = std::mem::replace(&mut scope.defined, before.0);
+                let then_known = std::mem::replace(&mut scope.known, before.1);
+                self.instructions(&if_query.otherwise, &format!("{}.else", at), scope);
+                if self.strict {
+                    scope.defined.retain(|group| then_defined.contains(group));
+                    scope.known.retain(|state| then_known.contains(state));
+                } else {
+                    scope.defined.extend(then_defined);
+                    scope.known.extend(then_known);
+                }
+            }
+            Instruction::While(while_query) => {
+                self.condition(&while_query.condition, query, at, scope);
+                let before = self
+                    .strict
+                    .then(|| (scope.defined.clone(), scope.known.clone()));
+                self.instructions(
+                    &while_query.instructions,
+                    &format!("{}.instructions", at),
+                    scope,
+                );
+                if let Some(before) = before {
+                    (scope.defined, scope.known) = before;
+                }
+            }
+            Instruction::Call(call) => {
+                let Some(summary) = self.summarize(&call.name) else {
+                    if !self.macros.contains_key(&call.name) {
+                        self.issues.push(Issue::UndefinedMacro {
+                            name: call.name.clone(),
+                            query: query.to_string(),
+                            at: at.to_string(),
+                        });
+                    }
+                    return;
+                };
+
+                let mut bind: Vec<(&String, &String)> = call.bind.iter().collect();
+                bind.sort();
+                for (param, group) in &bind {
+                    if summary.needs.contains(*param) {
+                        self.read(group, query, at, scope);
+                    }
+                }
+                for (param, group) in &bind {
+                    if summary.sets.contains(*param) {
+                        self.set(group, query, at, scope);
+                    }
+                }
+            }
+            Instruction::LoopOver(loop_over) => {
+                self.read(&loop_over.get_group, query, at, scope);
+                let dependency = if loop_over.parallel {
+                    iteration_dependency(
+                        &loop_over.instructions,
+                        &loop_over.iterator_name,
+                        loop_over.index_name.as_deref(),
+                        self.macros,
+                    )
+                } else {
+                    None
+                };
+                if let Some(reason) = dependency {
+                    self.issues.push(Issue::DependentIterations {
+                        reason,
+                        query: query.to_string(),
+                        at: at.to_string(),
+                    });
+                }
+
+                let before = self
+                    .strict
+                    .then(|| (scope.defined.clone(), scope.known.clone()));
+                self.set(&loop_over.iterator_name, query, at, scope);
+                self.instructions(
+                    &loop_over.instructions,
+                    &format!("{}.instructions", at),
+                    scope,
+                );
+                if let Some(before) = before {
+                    (scope.defined, scope.known) = before;
+                }
+                if let Some(collect) = &loop_over.collect {
+                    self.read(&collect.get_group, query, at, scope);
+                    self.set(&collect.set_group, query, at, scope);
+                }
+            }
+            Instruction::Checkpoint(_) => {
+                scope.early.push(State::Checkpoints);
+                scope.written.insert(State::Checkpoints);
+            }
+            Instruction::Rollback(rollback) => {
+                scope.early.push(State::Checkpoints);
+                scope.written.insert(State::Checkpoints);
+                if !rollback.check.trim().is_empty() {
+                    self.code(&rollback.check, &[], query, at, scope);
+                }
+            }
+            Instruction::Text(text) => {
+                self.code(&text.size, &[], query, at, scope);
+                self.code(&text.x, &[], query, at, scope);
+                self.code(&text.y, &[], query, at, scope);
+                if !text.tolerance.trim().is_empty() {
+                    self.code(&text.tolerance, &[], query, at, scope);
+                }
+                self.set(&text.set_group, query, at, scope);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Punctuation(char),
+    Other,
+}
+
+fn tokenize(code: &str) -> Vec<Token> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' || c == '"' || c == '`' {
+            let mut value = String::new();
+            let mut escaped = false;
+            i += 1;
+            while i < chars.len() && (escaped || chars[i] != c) {
+                if !escaped && chars[i] == '\\' {
+                    escaped = true;
+                } else {
+                    value.push(chars[i]);
+                    escaped = false;
+                }
+                i += 1;
+            }
+            i += 1;
+            // A template literal could hold anything, so it doesn't name a group
+            tokens.push(if c == '`' {
+                Token::Other
+            } else {
+                Token::String(value)
+            });
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Other);
+        } else {
+            tokens.push(Token::Punctuation(c));
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// The names JS code reads as variables, which leaves out properties like the `x` of `p.x`.
+fn identifiers(code: &str) -> Vec<String> {
+    let tokens = tokenize(code);
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index == 0 || tokens[index - 1] != Token::Punctuation('.'))
+        .filter_map(|(_, token)| match token {
+            Token::Identifier(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The group names passed as string literals to the builtins in `code`, each with the
+/// builtin and whether the call works without the group, which only `len('group')` does.
+fn group_literals(code: &str) -> Vec<(String, String, bool)> {
+    let tokens = tokenize(code);
+    let mut groups = Vec::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        let Token::Identifier(name) = token else {
+            continue;
+        };
+        if !GROUP_BUILTINS.contains(&name.as_str())
+            || tokens.get(index + 1) != Some(&Token::Punctuation('('))
+            || (index > 0 && tokens[index - 1] == Token::Punctuation('.'))
+        {
+            continue;
+        }
+
+        // Split the arguments on the commas that aren't nested any deeper
+        let mut args: Vec<Vec<&Token>> = vec![Vec::new()];
+        let mut depth = 0;
+        for token in &tokens[index + 2..] {
+            match token {
+                Token::Punctuation('(' | '[' | '{') => depth += 1,
+                Token::Punctuation(')' | ']' | '}') if depth == 0 => break,
+                Token::Punctuation(')' | ']' | '}') => depth -= 1,
+                Token::Punctuation(',') if depth == 0 => {
+                    args.push(Vec::new());
+                    continue;
+                }
+                _ => {}
+            }
+            args.last_mut().unwrap().push(token);
+        }
+
+        let optional = name == "len" && args.len() == 1;
+        for (position, arg) in args.iter().enumerate() {
+            if position > 0 && name != "distance" {
+                break;
+            }
+            if let [Token::String(group)] = arg.as_slice() {
+                groups.push((name.clone(), group.clone(), optional));
+            }
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let mut pipeline = Pipeline::from_json(
+            r#"{
+                "macros": {
+                    "holes": {
+                        "params": ["letters", "result"],
+                        "instructions": [
+                            {
+                                "type": "Filter",
+                                "set_group": "result",
+                                "get_group": "letters",
+                                "code": "depth(i) > 0"
+                            }
+                        ]
+                    }
+                },
+                "queries": [
+                    {
+                        "type": "GroupBy",
+                        "set_group": "letters",
+                        "get_group": "main",
+                        "code": "depth(i) == 0 && distance(i, 'main', j) == 0"
+                    },
+                    {
+                        "type": "Filter",
+                        "set_group": "outsidse_box",
+                        "get_group": "letters",
+                        "code": "len('outside_box') > 0 && frame('letters', i, 0).width >"
+                    },
+                    {
+                        "type": "Call",
+                        "name": "holes",
+                        "bind": { "letters": "letters", "result": "holes" }
+                    },
+                    {
+                        "type": "If",
+                        "condition": "len('holes') > 0",
+                        "then": [
+                            {
+                                "type": "Flatten",
+                                "set_group": "output",
+                                "get_group": "holes"
+                            }
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let issues = pipeline.validate_with_outputs(&["output"]);
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(
+            &issues[0],
+            Issue::SyntaxError { at, .. } if at == "queries[1]"
+        ));
+        assert_eq!(
+            issues[1],
+            Issue::UnusedGroup {
+                group: "outsidse_box".into(),
+                query: "Filter".into(),
+                at: "queries[1]".into(),
+            }
+        );
+
+        let Instruction::Filter(filter) = &mut pipeline.queries[1] else {
+            unreachable!();
+        };
+        filter.code = "len('outside_box') > 0 && frame('letters', i, 0).width > 1".into();
+
+        // `len` of a group that isn't set is 0, so it guards the group
+        assert_eq!(
+            pipeline.validate_with_outputs(&["output", "outsidse_box"]),
+            vec![]
+        );
+
+        let Instruction::Filter(filter) = &mut pipeline.queries[1] else {
+            unreachable!();
+        };
+        filter.code = "len('outside_box', 0) > 0".into();
+        let Instruction::If(if_query) = &mut pipeline.queries[3] else {
+            unreachable!();
+        };
+        if_query.condition = "'box' in globals && frame('box', 0, 0).width > 0".into();
+
+        let issues = pipeline.validate_with_outputs(&["output", "outsidse_box"]);
+        assert_eq!(
+            issues,
+            vec![
+                Issue::UndefinedGroup {
+                    group: "outside_box".into(),
+                    query: "Filter".into(),
+                    at: "queries[1]".into(),
+                },
+                Issue::MaybeUndefinedGroup {
+                    group: "box".into(),
+                    query: "If".into(),
+                    at: "queries[3]".into(),
+                },
+            ]
+        );
+        assert!(issues[0].is_error());
+        assert!(!issues[1].is_error());
+    }
+
+    #[test]
+    fn parallel() {
+        let mut pipeline = Pipeline::from_json(
+            r#"{
+                "queries": [
+                    {
+                        "type": "LoopOver",
+                        "get_group": "main",
+                        "iterator_name": "iter",
+                        "index_name": "index",
+                        "parallel": true,
+                        "instructions": [
+                            { "type": "Let", "name": "total", "code": "index" },
+                            {
+                                "type": "If",
+                                "condition": "index > 0",
+                                "then": [{ "type": "Let", "name": "total", "code": "total + 1" }]
+                            },
+                            {
+                                "type": "Filter",
+                                "set_group": "kept",
+                                "get_group": "iter",
+                                "code": "total > 1"
+                            }
+                        ],
+                        "collect": { "set_group": "all_kept", "get_group": "kept" }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(pipeline.validate_with_outputs(&["all_kept"]), vec![]);
+
+        // Without the first Let, an iteration that skips the If reads the last one's total
+        let Instruction::LoopOver(loop_over) = &mut pipeline.queries[0] else {
+            unreachable!();
+        };
+        loop_over.instructions.remove(0);
+        let issues = pipeline.validate_with_outputs(&["all_kept"]);
+        assert_eq!(
+            issues,
+            vec![Issue::DependentIterations {
+                reason: "iterations read 'total' before their Let sets it".into(),
+                query: "LoopOver".into(),
+                at: "queries[0]".into(),
+            }]
+        );
+        assert!(issues[0].is_error());
+    }
+}
@@ -0,0 +1,214 @@
+use geo::{Coord, LineString, Polygon, coord};
+
+use crate::Data;
+
+fn ring_to_wkt(ring: &LineString) -> String {
+    let points: Vec<String> = ring
+        .0
+        .iter()
+        .map(|point| format!("{} {}", point.x, point.y))
+        .collect();
+    format!("({})", points.join(", "))
+}
+
+/// Writes a polygon as a WKT `POLYGON`, like `POLYGON ((0 0, 1 0, 0 1, 0 0))`.
+pub fn polygon_to_wkt(polygon: &Polygon) -> String {
+    let rings: Vec<String> = std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(ring_to_wkt)
+        .collect();
+    format!("POLYGON ({})", rings.join(", "))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokens(wkt: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = wkt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_alphabetic() => {
+                let mut word = c.to_ascii_uppercase().to_string();
+                while let Some(next) = chars.peek().filter(|next| next.is_ascii_alphabetic()) {
+                    word.push(next.to_ascii_uppercase());
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(next) = chars.peek().filter(|next| {
+                    next.is_ascii_digit() || matches!(**next, '.' | 'e' | 'E' | '-' | '+')
+                }) {
+                    number.push(*next);
+                    chars.next();
+                }
+                let value = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number '{}' in WKT.", number))?;
+                tokens.push(Token::Number(value));
+            }
+            c => return Err(format!("Unexpected '{}' in WKT.", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(format!(
+                "Expected {:?} in WKT, found {:?}.",
+                expected, token
+            )),
+        }
+    }
+
+    /// A parenthesized list, calling `item` for each element.
+    fn list<T>(&mut self, item: impl Fn(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        self.expect(Token::Open)?;
+        let mut items = vec![item(self)?];
+        loop {
+            match self.next() {
+                Some(Token::Comma) => items.push(item(self)?),
+                Some(Token::Close) => return Ok(items),
+                token => return Err(format!("Expected ',' or ')' in WKT, found {:?}.", token)),
+            }
+        }
+    }
+
+    fn point(&mut self) -> Result<Coord, String> {
+        match (self.next(), self.next()) {
+            (Some(Token::Number(x)), Some(Token::Number(y))) => Ok(coord! {x: x, y: y}),
+            (x, y) => Err(format!("Expected a point in WKT, found {:?} {:?}.", x, y)),
+        }
+    }
+
+    fn polygon(&mut self) -> Result<Polygon, String> {
+        let mut rings = self.list(|parser| parser.list(Self::point).map(LineString::new))?;
+        let exterior = rings.remove(0);
+        Ok(Polygon::new(exterior, rings))
+    }
+
+    /// The next `POLYGON` or `MULTIPOLYGON`, `None` at the end.
+    fn geometry(&mut self) -> Result<Option<Vec<Polygon>>, String> {
+        let word = match self.next() {
+            None => return Ok(None),
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(format!("Expected a geometry in WKT, found {:?}.", token)),
+        };
+        if self.tokens.get(self.at) == Some(&Token::Word("EMPTY".to_string())) {
+            self.at += 1;
+            return Ok(Some(Vec::new()));
+        }
+
+        match word.as_str() {
+            "POLYGON" => Ok(Some(vec![self.polygon()?])),
+            "MULTIPOLYGON" => Ok(Some(self.list(Self::polygon)?)),
+            word => Err(format!("Unsupported geometry '{}' in WKT.", word)),
+        }
+    }
+}
+
+/// Reads every `POLYGON` and `MULTIPOLYGON` in `wkt`, one after the other.
+pub fn wkt_to_polygons(wkt: &str) -> Result<Vec<Polygon>, String> {
+    let mut parser = Parser {
+        tokens: tokens(wkt)?,
+        at: 0,
+    };
+
+    let mut polygons = Vec::new();
+    while let Some(geometry) = parser.geometry()? {
+        polygons.extend(geometry);
+    }
+    Ok(polygons)
+}
+
+impl Data {
+    /// Writes every shape of `groups` as WKT, one `POLYGON` per line.
+    pub fn to_wkt(&self, groups: &[&str]) -> String {
+        let shapes = self.shapes.lock().unwrap();
+        let data_groups = self.groups.lock().unwrap();
+
+        groups
+            .iter()
+            .filter_map(|name| data_groups.get(*name))
+            .flat_map(|group| group.iter().flatten())
+            .map(|index| polygon_to_wkt(&shapes[*index]) + "\n")
+            .collect()
+    }
+
+    /// Loads the polygons of a WKT text into `main`, see `wkt_to_polygons`.
+    pub fn from_wkt(wkt: &str) -> Result<Self, String> {
+        Ok(wkt_to_polygons(wkt)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Area, polygon};
+
+    use crate::*;
+
+    #[test]
+    fn it_works() {
+        let data = match Data::from_wkt(
+            "POLYGON ((0 0, 10 0, 10 10, 0 10, 0 0), (1 1, 2 1, 2 2, 1 1))
+            multipolygon (((20 0, 21 0, 21 1, 20 0)), ((1e1 -2.5, 11 -2.5, 11 -1.5, 10 -2.5)))
+            POLYGON EMPTY",
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("Error: {}", err);
+                assert!(false);
+                return;
+            }
+        };
+
+        let shapes = data.shapes.lock().unwrap().clone();
+        assert_eq!(shapes.len(), 3);
+        assert!(shapes.iter().any(|shape| shape.interiors().len() == 1));
+        let area: f64 = shapes.iter().map(|shape| shape.unsigned_area()).sum();
+        assert!((area - 99.5 - 0.5 - 0.5).abs() < 1e-9);
+
+        let triangle = polygon![(x: 0.0, y: 0.0), (x: 1.5, y: 0.0), (x: 0.0, y: -1.0)];
+        assert_eq!(
+            polygon_to_wkt(&triangle),
+            "POLYGON ((0 0, 1.5 0, 0 -1, 0 0))"
+        );
+        assert_eq!(
+            wkt_to_polygons(&polygon_to_wkt(&triangle)).unwrap(),
+            vec![triangle]
+        );
+        assert_eq!(data.to_wkt(&["main"]).lines().count(), 3);
+
+        assert!(wkt_to_polygons("POINT (0 0)").is_err());
+        assert!(wkt_to_polygons("POLYGON ((0 0, 1 0, 0 1, 0 0)").is_err());
+        assert!(wkt_to_polygons("POLYGON ((0 0, 1 0, 0 1, 0 @))").is_err());
+    }
+}
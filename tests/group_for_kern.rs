@@ -19,16 +19,16 @@ fn get_kerning_settings_outside_box() -> Vec<Box<dyn Query>> {
                 get_group: "main".into(),
                 code: "depth(group_index('main', i, 0)) > 2".into(),
             }),
-            Box::from(Sort {
-                set_group: "outer_symbols_not_braille_bottom_to_top".into(),
-                get_group: "outer_symbols_not_braille".into(),
-                compare: "frame('outer_symbols_not_braille', l).min_y < frame('outer_symbols_not_braille', r).min_y".into(),
-            }),
-            Box::from(Sort {
-                set_group: "outer_symbols_not_braille_left_to_right".into(),
-                get_group: "outer_symbols_not_braille".into(),
-                compare: "frame('outer_symbols_not_braille', l).min_x < frame('outer_symbols_not_braille', r).min_x".into(),
-            }),
+            Box::from(Sort::new(
+                "outer_symbols_not_braille_bottom_to_top",
+                "outer_symbols_not_braille",
+                "frame('outer_symbols_not_braille', l).min_y < frame('outer_symbols_not_braille', r).min_y",
+            )),
+            Box::from(Sort::new(
+                "outer_symbols_not_braille_left_to_right",
+                "outer_symbols_not_braille",
+                "frame('outer_symbols_not_braille', l).min_x < frame('outer_symbols_not_braille', r).min_x",
+            )),
             Box::from(GroupBy {
                 set_group: "vertical_text".into(),
                 get_group: "outer_symbols_not_braille_bottom_to_top".into(),
@@ -60,11 +60,11 @@ fn get_kerning_settings_outside_box() -> Vec<Box<dyn Query>> {
                 get_group: "main".into(),
                 code: "depth(group_index('main', i, 0)) % 2 == 0 && depth(group_index('main', i, 0)) > 0 && circle_metrics(group_index('main', i, 0)).circle > 0.9 && area(group_index('main', i, 0)) >= 0.001 && area(group_index('main', i, 0)) < 0.005".into(),
             }),
-            Box::from(Sort {
-                set_group: "braille".into(),
-                get_group: "braille".into(),
-                compare: "frame('braille', l).min_x < frame('braille', r).min_x".into(),
-            }),
+            Box::from(Sort::new(
+                "braille",
+                "braille",
+                "frame('braille', l).min_x < frame('braille', r).min_x",
+            )),
             Box::from(GroupBy {
                 set_group: "braille_group".into(),
                 get_group: "braille".into(),
@@ -114,16 +114,16 @@ fn get_kerning_settings_no_outside_box() -> Vec<Box<dyn Query>> {
             get_group: "main".into(),
             code: "depth(group_index('main', i, 0)) > 1".into(),
         }),
-        Box::from(Sort {
-            set_group: "outer_symbols_not_braille_bottom_to_top".into(),
-            get_group: "outer_symbols_not_braille".into(),
-            compare: "frame('outer_symbols_not_braille', l).min_y < frame('outer_symbols_not_braille', r).min_y".into(),
-        }),
-        Box::from(Sort {
-            set_group: "outer_symbols_not_braille_left_to_right".into(),
-            get_group: "outer_symbols_not_braille".into(),
-            compare: "frame('outer_symbols_not_braille', l).min_x < frame('outer_symbols_not_braille', r).min_x".into(),
-        }),
+        Box::from(Sort::new(
+            "outer_symbols_not_braille_bottom_to_top",
+            "outer_symbols_not_braille",
+            "frame('outer_symbols_not_braille', l).min_y < frame('outer_symbols_not_braille', r).min_y",
+        )),
+        Box::from(Sort::new(
+            "outer_symbols_not_braille_left_to_right",
+            "outer_symbols_not_braille",
+            "frame('outer_symbols_not_braille', l).min_x < frame('outer_symbols_not_braille', r).min_x",
+        )),
         Box::from(GroupBy {
             set_group: "horizontal_text".into(),
             get_group: "outer_symbols_not_braille_left_to_right".into(),
@@ -150,11 +150,11 @@ fn get_kerning_settings_no_outside_box() -> Vec<Box<dyn Query>> {
             get_group: "main".into(),
             code: "depth(group_index('main', i, 0)) % 2 == 1 && circle_metrics(group_index('main', i, 0)).circle > 0.9 && area(group_index('main', i, 0)) >= 0.001 && area(group_index('main', i, 0)) < 0.005".into(),
         }),
-        Box::from(Sort {
-            set_group: "braille".into(),
-            get_group: "braille".into(),
-            compare: "frame('braille', l).min_x < frame('braille', r).min_x".into(),
-        }),
+        Box::from(Sort::new(
+            "braille",
+            "braille",
+            "frame('braille', l).min_x < frame('braille', r).min_x",
+        )),
         Box::from(GroupBy {
             set_group: "braille_group".into(),
             get_group: "braille".into(),
@@ -265,7 +265,7 @@ fn test_kern() {
     let groups = data.groups.lock().unwrap();
     let shapes = data.shapes.lock().unwrap();
 
-    for (name, data) in groups.clone() {
+    for (name, data) in groups.iter() {
         if name == "braille" {
             assert_eq!(data.len(), 32);
         }